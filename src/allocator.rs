@@ -8,7 +8,10 @@ use x86_64::{
 };
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Sized for the largest screen mode, 1024x768 at a byte per pixel. The back
+/// buffer, the maximized console window and the console's cached rows take
+/// 768 KiB each, which leaves room for other windows, images and the shell.
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024; // 4 MiB
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
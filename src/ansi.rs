@@ -0,0 +1,199 @@
+use crate::vga_buffer::Color;
+
+/// The most parameters kept for a single control sequence.
+const MAX_PARAMS: usize = 8;
+
+/// Something decoded from a stream of text containing ANSI/VT100 escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A character to print, including control characters such as `\n`.
+    Print(char),
+    /// A control sequence `ESC [ params final`, with its parameters and final character.
+    Csi(Params, char),
+    /// `ESC 7`, which saves the cursor position.
+    SaveCursor,
+    /// `ESC 8`, which restores the saved cursor position.
+    RestoreCursor,
+}
+
+/// The numeric parameters of a control sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    len: usize,
+}
+
+impl Params {
+    fn new() -> Params {
+        Params {
+            values: [0; MAX_PARAMS],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, value: u16) {
+        if self.len < MAX_PARAMS {
+            self.values[self.len] = value;
+            self.len = self.len + 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns parameter `i`, or `default` if it was left out or zero.
+    pub fn get(&self, i: usize, default: u16) -> u16 {
+        if i < self.len && self.values[i] != 0 {
+            self.values[i]
+        } else {
+            default
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = u16> + 'a {
+        self.values[..self.len].iter().copied()
+    }
+}
+
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Splits text into printable characters and escape sequences.
+///
+/// Characters are fed in one at a time, so a sequence may be split over
+/// several writes.
+pub struct Parser {
+    state: State,
+    params: Params,
+    current: u16,
+    in_param: bool,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: Params::new(),
+            current: 0,
+            in_param: false,
+        }
+    }
+
+    /// Feeds the next character, returning an action once one is complete.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match self.state {
+            State::Ground => {
+                if c == '\u{001b}' {
+                    self.state = State::Escape;
+                    return None;
+                }
+                Some(Action::Print(c))
+            },
+            State::Escape => {
+                self.state = State::Ground;
+                match c {
+                    '[' => {
+                        self.state = State::Csi;
+                        self.params = Params::new();
+                        self.current = 0;
+                        self.in_param = false;
+                        None
+                    },
+                    '7' => Some(Action::SaveCursor),
+                    '8' => Some(Action::RestoreCursor),
+                    '\u{001b}' => {
+                        self.state = State::Escape;
+                        None
+                    },
+                    _ => None,
+                }
+            },
+            State::Csi => match c {
+                '0'..='9' => {
+                    let digit = c as u16 - '0' as u16;
+                    self.current = self.current.saturating_mul(10).saturating_add(digit);
+                    self.in_param = true;
+                    None
+                },
+                ';' => {
+                    self.params.push(self.current);
+                    self.current = 0;
+                    self.in_param = false;
+                    None
+                },
+                '\u{0040}'..='\u{007e}' => {
+                    if self.in_param || self.params.len() > 0 {
+                        self.params.push(self.current);
+                    }
+                    self.state = State::Ground;
+                    Some(Action::Csi(self.params, c))
+                },
+                // private markers and intermediate characters aren't used by anything we support.
+                '\u{0020}'..='\u{003f}' => None,
+                '\u{001b}' => {
+                    self.state = State::Escape;
+                    None
+                },
+                _ => {
+                    self.state = State::Ground;
+                    None
+                },
+            },
+        }
+    }
+}
+
+/// Maps one of the eight ANSI colors onto the VGA palette.
+pub fn color(index: u16, bright: bool) -> Color {
+    match (index, bright) {
+        (0, false) => Color::Black,
+        (1, false) => Color::Red,
+        (2, false) => Color::Green,
+        (3, false) => Color::Brown,
+        (4, false) => Color::Blue,
+        (5, false) => Color::Magenta,
+        (6, false) => Color::Cyan,
+        (7, false) => Color::LightGray,
+        (0, true) => Color::DarkGray,
+        (1, true) => Color::LightRed,
+        (2, true) => Color::LightGreen,
+        (3, true) => Color::Yellow,
+        (4, true) => Color::LightBlue,
+        (5, true) => Color::Pink,
+        (6, true) => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_parse_escape_sequences() {
+    serial_print!("test_parse_escape_sequences...");
+    let mut parser = Parser::new();
+    let actions: alloc::vec::Vec<Action> = "a\u{1b}[1;31mb\u{1b}[Hc\u{1b}7"
+        .chars()
+        .filter_map(|c| parser.advance(c))
+        .collect();
+
+    let mut bold_red = Params::new();
+    bold_red.push(1);
+    bold_red.push(31);
+
+    assert_eq!(actions, [
+        Action::Print('a'),
+        Action::Csi(bold_red, 'm'),
+        Action::Print('b'),
+        Action::Csi(Params::new(), 'H'),
+        Action::Print('c'),
+        Action::SaveCursor,
+    ]);
+    assert_eq!(bold_red.get(1, 0), 31);
+    assert_eq!(Params::new().get(0, 1), 1);
+    serial_println!("[ok]");
+}
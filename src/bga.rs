@@ -0,0 +1,174 @@
+use crate::{pci, vga_buffer::Framebuffer};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::{
+    instructions::port::Port,
+    structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, UnusedPhysFrame},
+    PhysAddr,
+    VirtAddr,
+};
+
+/// Selects the register `DATA` reads and writes.
+const INDEX: u16 = 0x1CE;
+const DATA: u16 = 0x1CF;
+
+const REG_ID: u16 = 0;
+const REG_XRES: u16 = 1;
+const REG_YRES: u16 = 2;
+const REG_BPP: u16 = 3;
+const REG_ENABLE: u16 = 4;
+const REG_VIRT_WIDTH: u16 = 6;
+const REG_VIRT_HEIGHT: u16 = 7;
+const REG_Y_OFFSET: u16 = 9;
+
+const ENABLED: u16 = 0x01;
+const LFB_ENABLED: u16 = 0x40;
+
+/// The PCI vendor and device IDs of the Bochs/QEMU standard VGA adapter.
+const PCI_VENDOR: u16 = 0x1234;
+const PCI_DEVICE: u16 = 0x1111;
+
+/// Where the linear framebuffer is mapped in virtual memory.
+pub const FRAMEBUFFER_START: usize = 0x_4444_8888_0000;
+/// How much of the framebuffer is mapped, enough for two pages of 1024x768 at 32 bits per pixel.
+pub const FRAMEBUFFER_SIZE: usize = 2 * MAX_WIDTH * MAX_HEIGHT * 4;
+
+/// The smallest mode with room for a usable console, the size of mode 13h.
+pub const MIN_WIDTH: usize = 320;
+pub const MIN_HEIGHT: usize = 200;
+/// The largest mode, which `FRAMEBUFFER_SIZE` has room for.
+pub const MAX_WIDTH: usize = 1024;
+pub const MAX_HEIGHT: usize = 768;
+
+static MAPPED: AtomicBool = AtomicBool::new(false);
+
+/// Why the adapter couldn't be set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BgaError {
+    /// There's no Bochs graphics adapter, such as without QEMU's `-vga std`.
+    NotFound,
+    /// `init` hasn't mapped the framebuffer.
+    NotMapped,
+    /// The framebuffer couldn't be mapped into memory.
+    MapFailed,
+    /// The mode is too large or has an unsupported color depth.
+    BadMode,
+}
+
+impl fmt::Display for BgaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BgaError::NotFound => write!(f, "no bochs graphics adapter found"),
+            BgaError::NotMapped => write!(f, "framebuffer is not mapped"),
+            BgaError::MapFailed => write!(f, "failed to map the framebuffer"),
+            BgaError::BadMode => write!(f, "unsupported mode"),
+        }
+    }
+}
+
+fn read(register: u16) -> u16 {
+    let mut index: Port<u16> = Port::new(INDEX);
+    let mut data: Port<u16> = Port::new(DATA);
+    unsafe {
+        index.write(register);
+        data.read()
+    }
+}
+
+fn write(register: u16, value: u16) {
+    let mut index: Port<u16> = Port::new(INDEX);
+    let mut data: Port<u16> = Port::new(DATA);
+    unsafe {
+        index.write(register);
+        data.write(value);
+    }
+}
+
+/// Whether a Bochs graphics adapter is present.
+pub fn available() -> bool {
+    let id = read(REG_ID);
+    id >= 0xB0C0 && id <= 0xB0C5
+}
+
+/// Maps the adapter's linear framebuffer, whose physical address is in its first PCI BAR.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), BgaError> {
+    if !available() {
+        return Err(BgaError::NotFound);
+    }
+    let device = pci::find(PCI_VENDOR, PCI_DEVICE).ok_or(BgaError::NotFound)?;
+    let physical = device.bar(0) as u64;
+
+    for offset in (0..FRAMEBUFFER_SIZE as u64).step_by(4096) {
+        let page = Page::containing_address(VirtAddr::new(FRAMEBUFFER_START as u64 + offset));
+        let frame = PhysFrame::containing_address(PhysAddr::new(physical + offset));
+        // video memory isn't ordinary RAM, so the frame allocator never hands it out.
+        let frame = unsafe { UnusedPhysFrame::new(frame) };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+        mapper.map_to(page, frame, flags, frame_allocator).map_err(|_| BgaError::MapFailed)?.flush();
+    }
+
+    MAPPED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Switches the adapter to a mode and returns its framebuffer. At 8 bits per
+/// pixel the colors come from the VGA palette as in mode 13h.
+pub fn set_mode(width: usize, height: usize, bits_per_pixel: usize) -> Result<Framebuffer, BgaError> {
+    if !MAPPED.load(Ordering::SeqCst) {
+        return Err(BgaError::NotMapped);
+    }
+    let bytes_per_pixel = match bits_per_pixel {
+        8 | 16 | 24 | 32 => bits_per_pixel / 8,
+        _ => return Err(BgaError::BadMode),
+    };
+    if width < MIN_WIDTH || height < MIN_HEIGHT || width > MAX_WIDTH || height > MAX_HEIGHT {
+        return Err(BgaError::BadMode);
+    }
+
+    write(REG_ENABLE, 0);
+    write(REG_XRES, width as u16);
+    write(REG_YRES, height as u16);
+    write(REG_BPP, bits_per_pixel as u16);
+    write(REG_ENABLE, ENABLED | LFB_ENABLED);
+
+    // the adapter may round the size, QEMU's down to a multiple of 8 wide.
+    let (width, height) = (read(REG_XRES) as usize, read(REG_YRES) as usize);
+    if width < MIN_WIDTH || height < MIN_HEIGHT || width > MAX_WIDTH || height > MAX_HEIGHT {
+        disable();
+        return Err(BgaError::BadMode);
+    }
+
+    // the adapter may pad rows, so ask it how wide they are.
+    let stride = read(REG_VIRT_WIDTH) as usize * bytes_per_pixel;
+
+    // flip between two pages if there's room for a second one below the first.
+    write(REG_VIRT_HEIGHT, 2 * height as u16);
+    write(REG_Y_OFFSET, 0);
+    let flip = if read(REG_VIRT_HEIGHT) as usize >= 2 * height && 2 * height * stride <= FRAMEBUFFER_SIZE {
+        Some(show_row as fn(usize))
+    } else {
+        None
+    };
+
+    Ok(Framebuffer {
+        address: FRAMEBUFFER_START,
+        width: width,
+        height: height,
+        stride: stride,
+        bytes_per_pixel: bytes_per_pixel,
+        flip: flip,
+    })
+}
+
+/// Scrolls the display so it starts at the given row of the framebuffer.
+fn show_row(row: usize) {
+    write(REG_Y_OFFSET, row as u16);
+}
+
+/// Turns the adapter off, going back to the VGA mode that was set before.
+pub fn disable() {
+    write(REG_ENABLE, 0);
+}
@@ -0,0 +1,210 @@
+use crate::{
+    vga_buffer::{self, Bitmap, Color, ScreenChar, Writer},
+    window::{self, WindowId, WINDOWS},
+};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The backend the console is drawn with, chosen by the `graphics` feature.
+#[cfg(feature = "graphics")]
+pub type Backend = Graphics;
+#[cfg(not(feature = "graphics"))]
+pub type Backend = crate::text_buffer::TextMode;
+
+/// Something `LunaRenderer` can draw the console on.
+///
+/// The screen is laid out as `view_lines` rows of output, a row for the
+/// scroll marker below them, and the input line at the bottom.
+pub trait Display {
+    /// The number of characters that fit on one row, at least 1.
+    fn columns(&self) -> usize;
+    /// The number of output rows shown, at least 1.
+    fn view_lines(&self) -> usize;
+    fn clear(&mut self);
+    /// Draws a character in an output row, or in the marker row at `view_lines`.
+    fn draw_char(&mut self, column: usize, row: usize, chr: ScreenChar);
+    fn draw_input_char(&mut self, column: usize, chr: ScreenChar);
+    /// Draws a bitmap line, as well as the display can.
+    fn draw_bitmap(&mut self, row: usize, bmp: &Bitmap);
+    /// Captures a drawn output row so it can be redrawn with `draw_bitmap`,
+    /// if that's quicker than drawing its characters again.
+    fn capture_row(&mut self, row: usize) -> Option<Bitmap>;
+    /// Forgets what's been drawn, so `present` draws everything again.
+    fn invalidate(&mut self);
+    /// Shows everything drawn since the last call.
+    fn present(&mut self);
+}
+
+/// What a row of the console shows.
+#[derive(Clone, PartialEq)]
+enum Row {
+    Chars(Vec<ScreenChar>),
+    Bitmap(Bitmap),
+}
+
+impl Row {
+    fn blinks(&self) -> bool {
+        match self {
+            Row::Chars(chars) => chars.iter().any(|chr| chr.attributes().blink),
+            Row::Bitmap(_) => false,
+        }
+    }
+}
+
+/// Draws the console as pixels on a window filling the screen, using the `Writer`'s font.
+///
+/// Drawing only records what each row shows. The rows that changed since
+/// they were last drawn are drawn together by `present`, so the rest of the
+/// window isn't touched.
+pub struct Graphics {
+    window: WindowId,
+    /// The output rows, the marker row and the input line.
+    rows: Vec<Row>,
+    /// What each row was last drawn with and whether blinking characters were
+    /// visible then, or `None` if it has to be drawn again.
+    drawn: Vec<Option<(Row, bool)>>,
+    input_row: usize,
+    /// The screen and cell size the rows are laid out for.
+    layout: ((usize, usize), (usize, usize)),
+}
+
+impl Graphics {
+    pub fn new() -> Graphics {
+        let (width, height) = vga_buffer::size();
+        let mut graphics = Graphics {
+            window: WINDOWS.lock().open_maximized("console", width, height).expect("screen too big for the console"),
+            rows: Vec::new(),
+            drawn: Vec::new(),
+            input_row: 0,
+            layout: ((0, 0), (0, 0)),
+        };
+        graphics.update_layout();
+        graphics
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        let font = vga_buffer::font();
+        (font.width(), font.height())
+    }
+
+    /// Makes room for every row on screen, and draws them all again if the
+    /// screen or font size changed.
+    fn update_layout(&mut self) {
+        let layout = (vga_buffer::size(), self.cell_size());
+        if layout != self.layout {
+            // rows move when the font size does, so clear what's between them too.
+            window::draw(self.window, |writer| writer.fill_buffer(Color::Black));
+            self.layout = layout;
+            self.invalidate();
+        }
+        self.input_row = self.view_lines() + 1;
+        self.rows.resize(self.input_row + 1, Row::Chars(Vec::new()));
+        self.drawn.resize(self.input_row + 1, None);
+    }
+
+    fn row_mut(&mut self, row: usize) -> &mut Row {
+        if row >= self.rows.len() {
+            self.rows.resize(row + 1, Row::Chars(Vec::new()));
+            self.drawn.resize(row + 1, None);
+        }
+        &mut self.rows[row]
+    }
+
+    fn set_char(&mut self, column: usize, row: usize, chr: ScreenChar) {
+        let row = self.row_mut(row);
+        if let Row::Bitmap(_) = row {
+            *row = Row::Chars(Vec::new());
+        }
+        if let Row::Chars(chars) = row {
+            if chars.len() <= column {
+                chars.resize(column + 1, ScreenChar::new(' ', Color::Black));
+            }
+            chars[column] = chr;
+        }
+    }
+
+    /// Draws the rows in a range that changed since they were last drawn, all
+    /// in one `window::draw`, then runs `then` on the window.
+    fn draw_rows<R, F: FnOnce(&mut Writer) -> R>(&mut self, rows: Range<usize>, then: F) -> Option<R> {
+        let rows = rows.start.min(self.rows.len())..rows.end.min(self.rows.len());
+        let blink_visible = vga_buffer::blink_visible();
+        let input_row = self.input_row;
+        let (current, drawn) = (&self.rows, &mut self.drawn);
+
+        window::draw(self.window, |writer| {
+            let (width, height) = (writer.font().width(), writer.font().height());
+            for row in rows {
+                let unchanged = match &drawn[row] {
+                    Some((shown, visible)) => *shown == current[row] && (*visible == blink_visible || !shown.blinks()),
+                    None => false,
+                };
+                if unchanged {
+                    continue;
+                }
+
+                let y = if row == input_row { writer.height().saturating_sub(height + 4) } else { height * row };
+                writer.draw_rect(0, y, writer.width(), height, Color::Black);
+                match &current[row] {
+                    Row::Chars(chars) => {
+                        for (column, &chr) in chars.iter().enumerate() {
+                            writer.draw_char(width * column, y, chr);
+                        }
+                    },
+                    Row::Bitmap(bmp) => writer.draw_bmp(0, y, bmp),
+                }
+                drawn[row] = Some((current[row].clone(), blink_visible));
+            }
+            then(writer)
+        })
+    }
+}
+
+impl Display for Graphics {
+    fn columns(&self) -> usize {
+        (vga_buffer::size().0 / self.cell_size().0).max(1)
+    }
+
+    fn view_lines(&self) -> usize {
+        let height = self.cell_size().1;
+        (vga_buffer::size().1.saturating_sub(4 + height) / height).saturating_sub(2).max(1)
+    }
+
+    fn clear(&mut self) {
+        self.update_layout();
+        for row in self.rows.iter_mut() {
+            *row = Row::Chars(Vec::new());
+        }
+    }
+
+    fn draw_char(&mut self, column: usize, row: usize, chr: ScreenChar) {
+        self.set_char(column, row, chr);
+    }
+
+    fn draw_input_char(&mut self, column: usize, chr: ScreenChar) {
+        self.set_char(column, self.input_row, chr);
+    }
+
+    fn draw_bitmap(&mut self, row: usize, bmp: &Bitmap) {
+        *self.row_mut(row) = Row::Bitmap(bmp.clone());
+    }
+
+    fn capture_row(&mut self, row: usize) -> Option<Bitmap> {
+        self.draw_rows(row..row + 1, |writer| {
+            let (width, height) = (writer.font().width(), writer.font().height());
+            let columns = (writer.width() / width).max(1);
+            writer.capture_bmp(0, height * row, width * columns, height)
+        })
+    }
+
+    fn invalidate(&mut self) {
+        for drawn in self.drawn.iter_mut() {
+            *drawn = None;
+        }
+    }
+
+    fn present(&mut self) {
+        self.update_layout();
+        self.draw_rows(0..self.rows.len(), |_| ());
+        window::present();
+    }
+}
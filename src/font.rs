@@ -0,0 +1,316 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+use font8x8::{BASIC_FONTS, BLOCK_FONTS, BOX_FONTS, GREEK_FONTS, LATIN_FONTS, UnicodeFonts};
+use lazy_static::lazy_static;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// The widest and tallest glyph a `Font` can have.
+pub const MAX_GLYPH_SIZE: usize = 32;
+
+lazy_static! {
+    static ref LUNA_8X16: Psf = Psf::parse(include_bytes!("../assets/luna8x16.psf")).unwrap();
+}
+
+/// The default font.
+pub static FONT8X8: Font8x8 = Font8x8;
+
+/// The names of the fonts built into the kernel.
+pub const BUILTIN_FONTS: [&str; 2] = ["8x8", "8x16"];
+
+/// Looks up a font built into the kernel by name.
+pub fn builtin(name: &str) -> Option<&'static dyn Font> {
+    match name {
+        "8x8" => Some(&FONT8X8),
+        "8x16" => Some(&*LUNA_8X16),
+        _ => None,
+    }
+}
+
+/// The pixels of one character, a row at a time with the leftmost pixel in the lowest bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyph {
+    rows: [u32; MAX_GLYPH_SIZE],
+}
+
+impl Glyph {
+    /// Create an empty glyph.
+    pub fn new() -> Glyph {
+        Glyph {
+            rows: [0; MAX_GLYPH_SIZE],
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize) {
+        self.rows[y] = self.rows[y] | 1 << x;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & 1 << x != 0
+    }
+}
+
+/// A fixed-size bitmap font.
+pub trait Font: Sync {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    /// The glyph for a character, if the font has one.
+    fn glyph(&self, c: char) -> Option<Glyph>;
+
+    /// Shown in place of characters the font has no glyph for. This is the
+    /// font's U+FFFD if it has one, or else an empty box.
+    fn fallback(&self) -> Glyph {
+        if let Some(glyph) = self.glyph('\u{fffd}') {
+            return glyph;
+        }
+
+        let mut glyph = Glyph::new();
+        let (width, height) = (self.width(), self.height());
+        if width < 4 || height < 4 {
+            return glyph;
+        }
+        for x in 1..width - 1 {
+            glyph.set(x, 1);
+            glyph.set(x, height - 2);
+        }
+        for y in 1..height - 1 {
+            glyph.set(1, y);
+            glyph.set(width - 2, y);
+        }
+        glyph
+    }
+
+    fn glyph_or_fallback(&self, c: char) -> Glyph {
+        self.glyph(c).unwrap_or_else(|| self.fallback())
+    }
+}
+
+/// The 8x8 font from the `font8x8` crate, covering Basic Latin, Latin-1,
+/// box drawing, block elements and Greek.
+pub struct Font8x8;
+
+impl Font for Font8x8 {
+    fn width(&self) -> usize {
+        8
+    }
+
+    fn height(&self) -> usize {
+        8
+    }
+
+    fn glyph(&self, c: char) -> Option<Glyph> {
+        let rows = BASIC_FONTS.get(c)
+            .or_else(|| LATIN_FONTS.get(c))
+            .or_else(|| BOX_FONTS.get(c))
+            .or_else(|| BLOCK_FONTS.get(c))
+            .or_else(|| GREEK_FONTS.get(c))?;
+        let mut glyph = Glyph::new();
+        for (y, row) in rows.iter().enumerate() {
+            glyph.rows[y] = *row as u32;
+        }
+        Some(glyph)
+    }
+}
+
+/// Why a PC Screen Font couldn't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// The data doesn't start with a PSF1 or PSF2 magic number.
+    BadMagic,
+    /// The data ends before the glyphs its header describes.
+    Truncated,
+    /// The glyphs are larger than `MAX_GLYPH_SIZE`.
+    TooLarge,
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::BadMagic => write!(f, "not a psf font"),
+            FontError::Truncated => write!(f, "font is truncated"),
+            FontError::TooLarge => write!(f, "glyphs are larger than {}x{}", MAX_GLYPH_SIZE, MAX_GLYPH_SIZE),
+        }
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, FontError> {
+    match data.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(FontError::Truncated),
+    }
+}
+
+/// A PC Screen Font, version 1 or 2, as used by the Linux console.
+pub struct Psf {
+    data: &'static [u8],
+    /// Where the first glyph starts in `data`.
+    offset: usize,
+    count: usize,
+    bytes_per_glyph: usize,
+    width: usize,
+    height: usize,
+    /// Which glyph each character uses. Without a table, characters index the glyphs directly.
+    unicode: BTreeMap<char, usize>,
+}
+
+impl Psf {
+    pub fn parse(data: &'static [u8]) -> Result<Psf, FontError> {
+        let mut font = if data.starts_with(&[0x36, 0x04]) {
+            let mode = *data.get(2).ok_or(FontError::Truncated)?;
+            let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+            Psf {
+                data: data,
+                offset: 4,
+                count: if mode & 0x01 != 0 { 512 } else { 256 },
+                bytes_per_glyph: height,
+                width: 8,
+                height: height,
+                unicode: BTreeMap::new(),
+            }
+        } else if data.starts_with(&[0x72, 0xb5, 0x4a, 0x86]) {
+            Psf {
+                data: data,
+                offset: u32_at(data, 8)? as usize,
+                count: u32_at(data, 16)? as usize,
+                bytes_per_glyph: u32_at(data, 20)? as usize,
+                width: u32_at(data, 28)? as usize,
+                height: u32_at(data, 24)? as usize,
+                unicode: BTreeMap::new(),
+            }
+        } else {
+            return Err(FontError::BadMagic);
+        };
+
+        if font.width > MAX_GLYPH_SIZE || font.height > MAX_GLYPH_SIZE {
+            return Err(FontError::TooLarge);
+        }
+        // the header's sizes are checked against the file, so a corrupt one can't overflow them.
+        let table = font.count.checked_mul(font.bytes_per_glyph)
+            .and_then(|glyphs| glyphs.checked_add(font.offset))
+            .ok_or(FontError::Truncated)?;
+        if font.bytes_per_glyph < font.height * ((font.width + 7) / 8) || data.len() < table {
+            return Err(FontError::Truncated);
+        }
+
+        if data[0] == 0x36 && data[2] & 0x06 != 0 {
+            font.read_psf1_table(&data[table..]);
+        } else if data[0] == 0x72 && u32_at(data, 12)? & 0x01 != 0 {
+            font.read_psf2_table(&data[table..]);
+        }
+        Ok(font)
+    }
+
+    /// Reads 16-bit code points, ending each glyph's list with 0xffff.
+    /// Multi-character sequences follow a 0xfffe and are skipped.
+    fn read_psf1_table(&mut self, table: &[u8]) {
+        let mut glyph = 0;
+        let mut in_sequence = false;
+        for pair in table.chunks_exact(2) {
+            match u16::from_le_bytes([pair[0], pair[1]]) {
+                0xffff => {
+                    glyph = glyph + 1;
+                    in_sequence = false;
+                },
+                0xfffe => in_sequence = true,
+                value if !in_sequence => {
+                    if let Some(c) = core::char::from_u32(value as u32) {
+                        self.unicode.entry(c).or_insert(glyph);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Reads UTF-8 characters, ending each glyph's list with 0xff.
+    /// Multi-character sequences follow a 0xfe and are skipped.
+    fn read_psf2_table(&mut self, table: &[u8]) {
+        for (glyph, entry) in table.split(|b| *b == 0xff).enumerate() {
+            let singles = entry.split(|b| *b == 0xfe).next().unwrap_or(&[]);
+            if let Ok(chars) = core::str::from_utf8(singles) {
+                for c in chars.chars() {
+                    self.unicode.entry(c).or_insert(glyph);
+                }
+            }
+        }
+    }
+}
+
+impl Font for Psf {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn glyph(&self, c: char) -> Option<Glyph> {
+        let index = if self.unicode.is_empty() {
+            c as usize
+        } else {
+            *self.unicode.get(&c)?
+        };
+        if index >= self.count {
+            return None;
+        }
+
+        // rows are padded to whole bytes, with the leftmost pixel in the highest bit.
+        let stride = (self.width + 7) / 8;
+        let start = self.offset + index * self.bytes_per_glyph;
+        let mut glyph = Glyph::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.data[start + y * stride + x / 8] & 0x80 >> (x % 8) != 0 {
+                    glyph.set(x, y);
+                }
+            }
+        }
+        Some(glyph)
+    }
+}
+
+#[test_case]
+fn test_font8x8_unicode_tables() {
+    serial_print!("test_font8x8_unicode_tables...");
+    for c in "Aé─█λ".chars() {
+        assert!(FONT8X8.glyph(c).is_some());
+    }
+
+    // characters without a glyph still show up as something.
+    assert_eq!(FONT8X8.glyph('\u{2603}'), None);
+    assert_ne!(FONT8X8.glyph_or_fallback('\u{2603}'), Glyph::new());
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_load_psf_font() {
+    serial_print!("test_load_psf_font...");
+    let font = builtin("8x16").unwrap();
+    assert_eq!((font.width(), font.height()), (8, 16));
+
+    // the built-in 8x16 font is the 8x8 one with every row doubled.
+    let tall = font.glyph('A').unwrap();
+    let short = FONT8X8.glyph('A').unwrap();
+    for y in 0..16 {
+        for x in 0..8 {
+            assert_eq!(tall.pixel(x, y), short.pixel(x, y / 2));
+        }
+    }
+
+    assert!(font.glyph('é').is_some());
+    assert_eq!(font.glyph('\u{2603}'), None);
+    assert_eq!(font.glyph_or_fallback('\u{2603}'), font.glyph('\u{fffd}').unwrap());
+    assert_eq!(Psf::parse(b"not a font").err(), Some(FontError::BadMagic));
+
+    // a PSF2 header claiming 2^32-1 glyphs of 2^32-1 bytes each, at the end of the file.
+    static HUGE: [u8; 32] = [
+        0x72, 0xb5, 0x4a, 0x86, 0, 0, 0, 0,
+        0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        8, 0, 0, 0, 8, 0, 0, 0,
+    ];
+    assert_eq!(Psf::parse(&HUGE).err(), Some(FontError::Truncated));
+    serial_println!("[ok]");
+}
@@ -48,3 +48,30 @@ pub fn char_hex_vec_to_int(chars: Vec<char>) -> u64 {
 
     return value;
 }
+
+/// Parses a hexadecimal string such as `ff` or `0x4444`, ignoring a leading `0x`.
+///
+/// Values too large for a `u64` saturate instead of overflowing.
+pub fn hex_str_to_int(s: &str) -> u64 {
+    let digits = if s.starts_with("0x") || s.starts_with("0X") { &s[2..] } else { s };
+
+    let mut value: u64 = 0;
+    for c in digits.chars() {
+        value = value.saturating_mul(16).saturating_add(char_to_hex(c));
+    }
+
+    return value;
+}
+
+#[test_case]
+fn test_hex_str_to_int() {
+    use crate::{serial_print, serial_println};
+
+    serial_print!("test_hex_str_to_int...");
+    assert_eq!(hex_str_to_int("ff"), 255);
+    assert_eq!(hex_str_to_int("0x4444"), 0x4444);
+    assert_eq!(hex_str_to_int("DeadBeef"), 0xdead_beef);
+    // too many digits for a u64.
+    assert_eq!(hex_str_to_int("ffffffffffffffffff"), u64::max_value());
+    serial_println!("[ok]");
+}
@@ -0,0 +1,202 @@
+use crate::vga_buffer::{Bitmap, Palette};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// Why an image file couldn't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// The file ended before the data its header describes.
+    Truncated,
+    /// The file doesn't start like a BMP or PCX file.
+    BadSignature,
+    /// A valid file, but in a variant we can't decode, such as 24-bit color.
+    Unsupported,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Truncated => write!(f, "image is truncated"),
+            ImageError::BadSignature => write!(f, "not a bmp or pcx image"),
+            ImageError::Unsupported => write!(f, "only 8-bit paletted images are supported"),
+        }
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ImageError> {
+    match data.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(ImageError::Truncated),
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ImageError> {
+    match data.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(ImageError::Truncated),
+    }
+}
+
+/// Decodes an uncompressed 8-bit Windows BMP.
+pub fn decode_bmp(data: &[u8]) -> Result<(Bitmap, Palette), ImageError> {
+    if !data.starts_with(b"BM") {
+        return Err(ImageError::BadSignature);
+    }
+
+    let pixels = u32_at(data, 10)? as usize;
+    let header_size = u32_at(data, 14)? as usize;
+    // the old OS/2 header has 16-bit sizes and no compression field.
+    if header_size < 40 {
+        return Err(ImageError::Unsupported);
+    }
+
+    let width = u32_at(data, 18)? as i32;
+    let height = u32_at(data, 22)? as i32;
+    let bits = u16_at(data, 28)?;
+    let compression = u32_at(data, 30)?;
+    if bits != 8 || compression != 0 || width <= 0 || height == 0 {
+        return Err(ImageError::Unsupported);
+    }
+
+    let mut palette = Palette::new();
+    let used = match u32_at(data, 46)? {
+        0 => 256,
+        n => (n as usize).min(256),
+    };
+    for i in 0..used {
+        let entry = 14 + header_size + i * 4;
+        let bgr = data.get(entry..entry + 3).ok_or(ImageError::Truncated)?;
+        palette.colors[i] = [bgr[2], bgr[1], bgr[0]];
+    }
+
+    // rows are padded to four bytes, and stored bottom row first unless the height is negative.
+    let (width, top_down) = (width as usize, height < 0);
+    let height = (height as i64).abs() as usize;
+    let stride = (width + 3) & !3;
+    // check the sizes against the file before allocating, so a corrupt header can't ask for too much.
+    let end = stride.checked_mul(height - 1)
+        .and_then(|rows| rows.checked_add(pixels))
+        .and_then(|last_row| last_row.checked_add(width));
+    match end {
+        Some(end) if end <= data.len() => {},
+        _ => return Err(ImageError::Truncated),
+    }
+    let mut bitmap = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let start = pixels + row * stride;
+        bitmap.extend_from_slice(data.get(start..start + width).ok_or(ImageError::Truncated)?);
+    }
+
+    Ok((Bitmap::new(width, height, bitmap), palette))
+}
+
+/// Decodes a run-length encoded 8-bit PCX image with its trailing 256 color palette.
+pub fn decode_pcx(data: &[u8]) -> Result<(Bitmap, Palette), ImageError> {
+    if data.len() < 128 || data[0] != 0x0a {
+        return Err(ImageError::BadSignature);
+    }
+    if data[2] != 1 || data[3] != 8 || data[65] != 1 {
+        return Err(ImageError::Unsupported);
+    }
+
+    let width = (u16_at(data, 8)? as usize + 1).saturating_sub(u16_at(data, 4)? as usize);
+    let height = (u16_at(data, 10)? as usize + 1).saturating_sub(u16_at(data, 6)? as usize);
+    let line = u16_at(data, 66)? as usize;
+    if width == 0 || height == 0 || line < width {
+        return Err(ImageError::Unsupported);
+    }
+
+    // the palette is the last 768 bytes, after a 0x0c marker.
+    if data.len() < 128 + 769 || data[data.len() - 769] != 0x0c {
+        return Err(ImageError::Truncated);
+    }
+    let mut palette = Palette::new();
+    for (i, rgb) in data[data.len() - 768..].chunks(3).enumerate() {
+        palette.colors[i] = [rgb[0], rgb[1], rgb[2]];
+    }
+
+    // runs are marked by the top two bits, and may carry over from one line to the next.
+    let encoded = &data[128..data.len() - 769];
+    // each byte decodes to at most 63 pixels, which bounds what a header can ask for.
+    let size = line * height;
+    if size / 63 > encoded.len() {
+        return Err(ImageError::Truncated);
+    }
+    let mut decoded = Vec::with_capacity(size);
+    let mut i = 0;
+    while decoded.len() < size {
+        let byte = *encoded.get(i).ok_or(ImageError::Truncated)?;
+        if byte & 0xc0 == 0xc0 {
+            let value = *encoded.get(i + 1).ok_or(ImageError::Truncated)?;
+            for _ in 0..(byte & 0x3f) {
+                decoded.push(value);
+            }
+            i = i + 2;
+        } else {
+            decoded.push(byte);
+            i = i + 1;
+        }
+    }
+
+    let mut bitmap = Vec::with_capacity(width * height);
+    for y in 0..height {
+        bitmap.extend_from_slice(&decoded[y * line..y * line + width]);
+    }
+
+    Ok((Bitmap::new(width, height, bitmap), palette))
+}
+
+/// Decodes a BMP or PCX image, telling them apart by their first bytes.
+pub fn decode(data: &[u8]) -> Result<(Bitmap, Palette), ImageError> {
+    if data.starts_with(b"BM") {
+        decode_bmp(data)
+    } else {
+        decode_pcx(data)
+    }
+}
+
+#[test_case]
+fn test_decode_images() {
+    serial_print!("test_decode_images...");
+    let (bmp, bmp_palette) = decode(include_bytes!("../assets/moon.bmp")).unwrap();
+    let (pcx, pcx_palette) = decode(include_bytes!("../assets/moon.pcx")).unwrap();
+
+    assert_eq!((bmp.width, bmp.height), (38, 30));
+    assert_eq!(bmp.data[0], 0);
+    assert_eq!(bmp.data[15 * 38 + 8], 1);
+    assert_eq!(bmp.data, pcx.data);
+    assert!(bmp_palette.colors[..] == pcx_palette.colors[..]);
+    assert_eq!(decode(b"BM").err(), Some(ImageError::Truncated));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_decode_huge_truncated_images() {
+    use alloc::vec;
+
+    serial_print!("test_decode_huge_truncated_images...");
+    // a BMP header claiming 2^31-1 by 2^31-1 pixels, with one palette entry and no pixels.
+    let mut bmp = vec![0u8; 58];
+    bmp[..2].copy_from_slice(b"BM");
+    bmp[10..14].copy_from_slice(&58u32.to_le_bytes());
+    bmp[14..18].copy_from_slice(&40u32.to_le_bytes());
+    bmp[18..22].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    bmp[22..26].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    bmp[28..30].copy_from_slice(&8u16.to_le_bytes());
+    bmp[46..50].copy_from_slice(&1u32.to_le_bytes());
+    assert_eq!(decode(&bmp).err(), Some(ImageError::Truncated));
+
+    // a PCX header claiming 65535 by 65536 pixels, with a palette and no pixels.
+    let mut pcx = vec![0u8; 128 + 769];
+    pcx[..4].copy_from_slice(&[0x0a, 5, 1, 8]);
+    pcx[8..12].copy_from_slice(&[0xfe, 0xff, 0xff, 0xff]);
+    pcx[65] = 1;
+    pcx[66..68].copy_from_slice(&[0xff, 0xff]);
+    pcx[128] = 0x0c;
+    assert_eq!(decode(&pcx).err(), Some(ImageError::Truncated));
+    serial_println!("[ok]");
+}
@@ -1,4 +1,4 @@
-use crate::{gdt, hlt_loop, serial_print, rect, vga_apply, vga_buffer::Color, LUSHKeyHandler};
+use crate::{gdt, hlt_loop, mouse, serial, serial_print, rect, vga_apply, vga_buffer::Color, task::input::{self, Input}};
 use lazy_static::lazy_static;
 use pic8259_simple::ChainedPics;
 use spin;
@@ -12,6 +12,8 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial = PIC_1_OFFSET + 4,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
 impl InterruptIndex {
@@ -27,6 +29,22 @@ impl InterruptIndex {
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+/// Unmasks an IRQ line on the chained PICs, which keep whatever mask the BIOS left.
+pub fn unmask_irq(irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    let mut data: Port<u8> = Port::new(if irq < 8 { 0x21 } else { 0xA1 });
+    unsafe {
+        let mask = data.read();
+        data.write(mask & !(1 << (irq % 8)));
+    }
+
+    if irq >= 8 {
+        // the secondary PIC is chained through IRQ 2.
+        unmask_irq(2);
+    }
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -39,6 +57,8 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Serial.as_usize()].set_handler_fn(serial_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
         idt
     };
 }
@@ -78,43 +98,55 @@ extern "x86-interrupt" fn double_fault_handler(
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    let now = crate::time::tick();
+    crate::task::timer::tick(now);
+
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
 }
 
+// the input handlers only queue what they read. the `InputTask` decodes it and
+// runs the shell, so a handler never waits on a lock the shell is holding.
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, Keyboard, ScancodeSet1};
-    use spin::Mutex;
     use x86_64::instructions::port::Port;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1));
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    input::push(Input::Scancode(scancode));
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
+}
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    while let Some(byte) = serial::receive() {
+        input::push(Input::Serial(byte));
+    }
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(key) => LUSHKeyHandler!(key),
-                DecodedKey::RawKey(key) => {},
-            }
-        }
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    if let Some(byte) = mouse::receive() {
+        input::push(Input::Mouse(byte));
     }
 
     unsafe {
         PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
     }
 }
 
 #[cfg(test)]
-use crate::{serial_print, serial_println};
+use crate::serial_println;
 
 #[test_case]
 fn test_breakpoint_exception() {
@@ -3,6 +3,7 @@
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
+#![feature(asm)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
@@ -11,13 +12,26 @@ extern crate alloc;
 use core::panic::PanicInfo;
 use linked_list_allocator::LockedHeap;
 
+pub mod ansi;
+pub mod bga;
 pub mod hex;
+pub mod image;
 pub mod allocator;
+pub mod display;
+pub mod font;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod mouse;
+pub mod palette;
+pub mod pci;
+pub mod rtc;
 pub mod serial;
+pub mod task;
+pub mod text_buffer;
+pub mod time;
 pub mod vga_buffer;
+pub mod window;
 pub mod shell;
 
 #[global_allocator]
@@ -27,6 +41,13 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    time::init();
+    serial::init();
+    palette::init();
+    interrupts::unmask_irq(4);
+    if mouse::init().is_ok() {
+        interrupts::unmask_irq(12);
+    }
     x86_64::instructions::interrupts::enable();
 }
 
@@ -75,8 +96,17 @@ entry_point!(test_kernel_main);
 
 /// Entry point for `cargo xtest`
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
     init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
     test_main();
     hlt_loop();
 }
@@ -7,37 +7,73 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec, vec::Vec};
 use luna::{
     println,
     print,
-    raw_pixel,
-    rect,
     bmp,
     draw_char,
     color,
     fill_buffer,
-    cap_bmp,
+    draw_line,
+    circle,
+    fill_circle,
+    ellipse,
+    fill_ellipse,
+    triangle,
+    fill_triangle,
+    polygon,
+    fill_polygon,
+    flood_fill,
     vga_apply,
+    set_font,
     vga_buffer::Color,
+    vga_buffer::Framebuffer,
+    vga_buffer::WRITER,
+    bga,
     vga_buffer::Bitmap,
     vga_buffer::ScreenChar,
-    hex::char_hex_vec_to_int,
+    hex::hex_str_to_int,
+    font,
+    image,
+    palette,
+    rtc,
+    time,
     LUSHKeyHandler,
     LUSHAddCommand,
+    LUSHComplete,
     lush_keypush,
     lush_keypop,
+    lush_mousepush,
+    lush_mousepop,
     lure_enabled,
     lure_bmp,
+    lure_clock,
+    lure_redraw,
+    lure_scrollback,
+    shell::LunaKey,
+    mouse::MouseEvent,
+    window,
     shell::LunaRenderer,
-    shell::LunaLine
+    shell::LunaLine,
+    shell::ClockTask,
+    shell::BlinkTask,
+    task::{executor::Executor, input::InputTask, Task}
 };
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
+use core::time::Duration;
 use lazy_format::lazy_format;
+use lazy_static::lazy_static;
+use spin::Mutex;
 
 entry_point!(kernel_main);
 
+lazy_static! {
+    /// Addresses handed out by the `set` command, offered as completions for `get`.
+    static ref SET_ADDRESSES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+}
+
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     use luna::allocator;
     use luna::memory::{self, BootInfoFrameAllocator};
@@ -50,46 +86,84 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    /// Switches the screen mode, going back to mode 13h for 320x200 at 8 bits per pixel.
+    fn switch_mode(width: usize, height: usize, bits_per_pixel: usize) -> Result<(), bga::BgaError> {
+        let framebuffer = if (width, height, bits_per_pixel) == (320, 200, 8) {
+            bga::disable();
+            Framebuffer::mode13h()
+        } else {
+            bga::set_mode(width, height, bits_per_pixel)?
+        };
+
+        let mut writer = WRITER.lock();
+        writer.set_framebuffer(framebuffer);
+        if framebuffer.bytes_per_pixel > 1 {
+            // there's no hardware palette, so keep showing the colors it had.
+            writer.set_palette(&palette::save());
+        }
+        Ok(())
+    }
+
+    // a bigger screen when the Bochs/QEMU adapter is there, otherwise stay in mode 13h.
+    if cfg!(feature = "graphics") && bga::init(&mut mapper, &mut frame_allocator).is_ok() {
+        let _ = switch_mode(640, 480, 8);
+    }
     
-    fn memchk_handler(args: Vec<char>) {
-        println!("args@{:p}", args.as_slice());
+    fn memchk_handler(args: &[String]) {
+        println!("args@{:p}", args.as_ptr());
         println!("newV@{:p}", vec![args.len()].as_slice());      
-        println!("boxV@{:p}", Box::into_raw(Box::new(args)));      
+        println!("boxV@{:p}", Box::into_raw(Box::new(args.to_vec())));      
     }
-    LUSHAddCommand!(vec!['m', 'e', 'm', 'c', 'h', 'k'], memchk_handler);
+    LUSHAddCommand!("memchk", memchk_handler, "memchk [args...]", "show some heap addresses");
 
-    fn set_handler(args: Vec<char>) {
-        let set_value = Box::into_raw(Box::new(args));
+    fn set_handler(args: &[String]) {
+        let set_value = Box::into_raw(Box::new(args.join(" ")));
+        SET_ADDRESSES.lock().push(set_value as usize);
         print!("address: {:p}", set_value); 
     }
-    LUSHAddCommand!(vec!['s', 'e', 't'], set_handler);
+    LUSHAddCommand!("set", set_handler, "set <text...>", "store text on the heap");
 
-    fn get_handler(args: Vec<char>) {
-        let addr = char_hex_vec_to_int(args);
-        let charvec = unsafe { &mut *(addr as *mut Vec<char>) };
-        for i in charvec {
-            print!("{}", i)
+    fn get_handler(args: &[String]) {
+        if args.len() != 1 {
+            color!(Color::LightRed);
+            print!("usage: get <address>");
+            return;
         }
+        let addr = hex_str_to_int(&args[0]);
+        let value = unsafe { &*(addr as *const String) };
+        print!("{}", value);
     }
-    LUSHAddCommand!(vec!['g', 'e', 't'], get_handler);
+    LUSHAddCommand!("get", get_handler, "get <address>", "print text stored by set");
 
-    fn hex_handler(args: Vec<char>) {
-        print!("{}", char_hex_vec_to_int(args));
+    fn complete_address(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() > 0 {
+            return Vec::new();
+        }
+        SET_ADDRESSES.lock().iter().map(|addr| format!("{:#x}", addr)).collect()
     }
-    LUSHAddCommand!(vec!['h', 'e', 'x'], hex_handler);
-    
-    fn echo_handler(args: Vec<char>) {
-        // print each character in the remaining string.
-        for i in args {
-            print!("{}", i);
+    LUSHComplete!("get", complete_address);
+
+    fn hex_handler(args: &[String]) {
+        for arg in args {
+            print!("{} ", hex_str_to_int(arg));
         }
     }
-    LUSHAddCommand!(vec!['e', 'c', 'h', 'o'], echo_handler);
+    LUSHAddCommand!("hex", hex_handler, "hex <value...>", "convert hex to decimal");
+    
+    fn echo_handler(args: &[String]) {
+        print!("{}", args.join(" "));
+    }
+    LUSHAddCommand!("echo", echo_handler, "echo [text...]", "print the arguments");
 
-    fn lusc_handler(args: Vec<char>) {
-        // print each character in the remaining string.
+    /// How long a `lusc` program may run before it's stopped.
+    const LUSC_TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn lusc_handler(args: &[String]) {
+        // the program may be split over several arguments.
+        let args: Vec<char> = args.concat().chars().collect();
         let mut cells = vec![0 as i32];
-        let mut iters = 0;
+        let start = time::Instant::now();
         let mut i = 0;
         let mut j = 0;
         let mut jump = 0;
@@ -103,9 +177,9 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
                 done = true;
                 break;
             }
-            if iters > 1000000 {
+            if start.elapsed() > LUSC_TIMEOUT {
                 color!(Color::LightRed);
-                println!("max iterations reached.");
+                println!("time limit reached.");
                 color!(Color::LightGray);
                 done = false;
                 break;
@@ -179,7 +253,6 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
             }
 
             i = i + 1;
-            iters = iters + 1;
         }
 
         if charsPrinted > 0 {
@@ -192,61 +265,516 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
         }
         print!("]");
     }
-    LUSHAddCommand!(vec!['l', 'u', 's', 'c'], lusc_handler);
+    LUSHAddCommand!("lusc", lusc_handler, "lusc <program...>", "run a lusc cell program");
 
 
-    fn keyown_key_handler(key: char) {
-        if key == '\u{001b}' {
-            rect!(0,0,319,199,Color::Black);
+    fn keyown_key_handler(key: LunaKey) {
+        if key == LunaKey::Char('\u{001b}') {
+            fill_buffer!(Color::Black);
             lush_keypop!();
             lure_enabled!(true);
         }
         vga_apply!();
     }
-    fn keyown_handler(args: Vec<char>) {
-        rect!(0,0,319,199,Color::DarkGray);
+    fn keyown_handler(args: &[String]) {
+        fill_buffer!(Color::DarkGray);
         vga_apply!();
         lush_keypush!(keyown_key_handler);
         lure_enabled!(false);
     }
-    LUSHAddCommand!(vec!['k', 'e', 'y', 'o', 'w', 'n'], keyown_handler);
+    LUSHAddCommand!("keyown", keyown_handler, "keyown", "own the keyboard until esc");
 
-    fn colors_handler(args: Vec<char>) {
-        fill_buffer!(Color::Black);
-        for y in 0..24 {
-            for x in 0..24 {
-                raw_pixel!(x * 2, y * 2, (24 * y + x) as u8);
+    fn colors_handler(args: &[String]) {
+        // drawn off screen, so whatever is showing is left alone.
+        let mut swatches = Bitmap::new(319, 48, vec![Color::Black as u8; 319 * 48]);
+        let (lines, _) = WRITER.lock().draw_on(&mut swatches, |writer| {
+            for y in 0..24 {
+                for x in 0..24 {
+                    writer.write_raw_pixel(x * 2, y * 2, (24 * y + x) as u8);
+                }
             }
+            (0..48).step_by(8).map(|y| writer.capture_bmp(0, y, 319, 8)).collect::<Vec<Bitmap>>()
+        });
+
+        for line in lines {
+            lure_bmp!(line);
         }
-        
-        lure_bmp!(cap_bmp!(0,0,319,8));
-        lure_bmp!(cap_bmp!(0,8,319,8));
-        lure_bmp!(cap_bmp!(0,16,319,8));
-        lure_bmp!(cap_bmp!(0,24,319,8));
-        lure_bmp!(cap_bmp!(0,32,319,8));
-        lure_bmp!(cap_bmp!(0,48,319,8));
     }
-    LUSHAddCommand!(vec!['c', 'o', 'l', 'o', 'r', 's'], colors_handler);
+    LUSHAddCommand!("colors", colors_handler, "colors", "show the colour palette");
 
-    fn color_handler(args: Vec<char>) {
-        fill_buffer!(Color::Black);
+    fn color_handler(args: &[String]) {
+        let c = match args.get(0) {
+            Some(arg) => Color::from_name(arg).map_or_else(|| hex_str_to_int(arg) as u8, |color| color as u8),
+            None => 0,
+        };
+
+        lure_bmp!(Bitmap::new(48, 8, vec![c; 48 * 8]));
+    }
+    LUSHAddCommand!("color", color_handler, "color <index|name>", "show one palette colour");
+
+    fn complete_color(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() > 0 {
+            return Vec::new();
+        }
+        Color::ALL.iter().map(|color| String::from(color.name())).collect()
+    }
+    LUSHComplete!("color", complete_color);
+
+    /// Shapes understood by `draw`, with their coordinates. Polygons take any number of points.
+    const SHAPES: [(&str, &str); 10] = [
+        ("line", "x0 y0 x1 y1"),
+        ("circle", "x y r"),
+        ("fcircle", "x y r"),
+        ("ellipse", "x y rx ry"),
+        ("fellipse", "x y rx ry"),
+        ("tri", "x0 y0 x1 y1 x2 y2"),
+        ("ftri", "x0 y0 x1 y1 x2 y2"),
+        ("poly", "x0 y0 x1 y1 x2 y2 ..."),
+        ("fpoly", "x0 y0 x1 y1 x2 y2 ..."),
+        ("fill", "x y"),
+    ];
+
+    fn canvas_key_handler(key: LunaKey) {
+        if key == LunaKey::Char('\u{001b}') {
+            lush_keypop!();
+            lure_enabled!(true);
+        }
+    }
+
+    fn draw_handler(args: &[String]) {
+        let shape = args.get(0).map_or("", |arg| arg.as_str());
+        let coords = match SHAPES.iter().find(|s| s.0 == shape) {
+            Some(s) => s.1,
+            None => {
+                color!(Color::LightRed);
+                println!("shapes: line circle fcircle ellipse fellipse tri ftri poly fpoly fill");
+                return;
+            },
+        };
+
+        // a colour name may follow the coordinates.
+        let (numbers, c) = match args.last().and_then(|arg| Color::from_name(arg)) {
+            Some(c) if args.len() > 1 => (&args[1..args.len() - 1], c),
+            _ => (&args[1.min(args.len())..], Color::White),
+        };
+
+        let n: Vec<isize> = numbers.iter()
+            .filter_map(|arg| arg.parse::<i16>().ok())
+            .map(|value| value as isize)
+            .collect();
+
+        let expected = coords.split(' ').filter(|word| *word != "...").count();
+        let polygon = shape.ends_with("poly");
+        if n.len() != numbers.len() || n.len() < expected || (polygon && n.len() % 2 != 0) || (!polygon && n.len() != expected) {
+            color!(Color::LightRed);
+            println!("usage: draw {} {} [color]", shape, coords);
+            return;
+        }
+
+        let points: Vec<(isize, isize)> = n.chunks(2).map(|p| (p[0], p[1])).collect();
+        match shape {
+            "line" => draw_line!(n[0], n[1], n[2], n[3], c),
+            "circle" => circle!(n[0], n[1], n[2], c),
+            "fcircle" => fill_circle!(n[0], n[1], n[2], c),
+            "ellipse" => ellipse!(n[0], n[1], n[2], n[3], c),
+            "fellipse" => fill_ellipse!(n[0], n[1], n[2], n[3], c),
+            "tri" => triangle!(n[0], n[1], n[2], n[3], n[4], n[5], c),
+            "ftri" => fill_triangle!(n[0], n[1], n[2], n[3], n[4], n[5], c),
+            "poly" => polygon!(&points, c),
+            "fpoly" => fill_polygon!(&points, c),
+            _ => {
+                if n[0] >= 0 && n[1] >= 0 {
+                    flood_fill!(n[0] as usize, n[1] as usize, c);
+                }
+            },
+        }
+
+        vga_apply!();
+        lush_keypush!(canvas_key_handler);
+        lure_enabled!(false);
+    }
+    LUSHAddCommand!("draw", draw_handler, "draw <shape> <x y...> [color]", "draw over the screen until esc");
+
+    fn complete_draw(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() == 0 {
+            return SHAPES.iter().map(|s| String::from(s.0)).collect();
+        }
+        Color::ALL.iter().map(|color| String::from(color.name())).collect()
+    }
+    LUSHComplete!("draw", complete_draw);
+
+    /// Images built into the kernel, for `show`.
+    const IMAGES: [(&str, &[u8]); 2] = [
+        ("moon.bmp", include_bytes!("../assets/moon.bmp")),
+        ("moon.pcx", include_bytes!("../assets/moon.pcx")),
+    ];
+
+    fn show_handler(args: &[String]) {
+        let data = match args.get(0).and_then(|arg| IMAGES.iter().find(|image| image.0 == arg.as_str())) {
+            Some(image) => image.1,
+            None => {
+                color!(Color::LightRed);
+                println!("images: moon.bmp moon.pcx");
+                return;
+            },
+        };
+
+        let (mut bitmap, palette) = match image::decode(data) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                color!(Color::LightRed);
+                println!("{}", e);
+                return;
+            },
+        };
+
+        // the hardware palette isn't changed, so use the closest of the default colors.
+        let table = palette.remap_table();
+        for pixel in bitmap.data.iter_mut() {
+            *pixel = table[*pixel as usize];
+        }
+
+        let width = bitmap.width.min(luna::vga_buffer::size().0);
+        for top in (0..bitmap.height).step_by(8) {
+            let mut strip = vec![0; width * 8];
+            for y in top..(top + 8).min(bitmap.height) {
+                let row = &bitmap.data[y * bitmap.width..y * bitmap.width + width];
+                strip[(y - top) * width..(y - top + 1) * width].copy_from_slice(row);
+            }
+            lure_bmp!(Bitmap::new(width, 8, strip));
+        }
+    }
+    LUSHAddCommand!("show", show_handler, "show <image>", "display a built-in bmp or pcx image");
+
+    fn complete_image(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() > 0 {
+            return Vec::new();
+        }
+        IMAGES.iter().map(|image| String::from(image.0)).collect()
+    }
+    LUSHComplete!("show", complete_image);
+
+    fn palette_handler(args: &[String]) {
+        let numbers: Vec<u8> = args.iter().skip(1).filter_map(|arg| arg.parse::<u8>().ok()).collect();
+        let complete = numbers.len() + 1 == args.len();
+
+        match (args.get(0).map_or("", |arg| arg.as_str()), numbers.len()) {
+            ("get", 1) if complete => {
+                let rgb = palette::read(numbers[0]);
+                println!("{}: {} {} {}", numbers[0], rgb[0], rgb[1], rgb[2]);
+            },
+            ("set", 4) if complete => palette::write(numbers[0], [numbers[1], numbers[2], numbers[3]]),
+            ("fade", _) => {
+                let saved = palette::save();
+                palette::fade_out(32);
+                palette::fade_in(&saved, 32);
+            },
+            ("cycle", 2) if complete => {
+                for _ in 0..120 {
+                    palette::wait_for_retrace();
+                    palette::cycle(numbers[0], numbers[1]);
+                }
+            },
+            ("load", _) => {
+                match args.get(1).and_then(|arg| IMAGES.iter().find(|image| image.0 == arg.as_str())) {
+                    Some(image) => match image::decode(image.1) {
+                        Ok((_, colors)) => palette::load(&colors),
+                        Err(e) => {
+                            color!(Color::LightRed);
+                            println!("{}", e);
+                        },
+                    },
+                    None => {
+                        color!(Color::LightRed);
+                        println!("images: moon.bmp moon.pcx");
+                    },
+                }
+            },
+            ("reset", _) => palette::reset(),
+            _ => {
+                color!(Color::LightRed);
+                println!("usage: palette get <i> | set <i> <r> <g> <b> | fade | cycle <first> <last> | load <image> | reset");
+            },
+        }
+    }
+    LUSHAddCommand!("palette", palette_handler, "palette <get|set|fade|cycle|load|reset> [args...]", "read or change the vga palette");
+
+    fn complete_palette(args: &[String], _word: &str) -> Vec<String> {
+        match args.len() {
+            0 => ["get", "set", "fade", "cycle", "load", "reset"].iter().map(|s| String::from(*s)).collect(),
+            1 if args[0] == "load" => IMAGES.iter().map(|image| String::from(image.0)).collect(),
+            _ => Vec::new(),
+        }
+    }
+    LUSHComplete!("palette", complete_palette);
+
+    fn font_handler(args: &[String]) {
+        let name = args.get(0).map_or("", |arg| arg.as_str());
+        match font::builtin(name) {
+            Some(f) => {
+                set_font!(f);
+                lure_redraw!();
+            },
+            None => {
+                let current = luna::vga_buffer::font();
+                println!("font: {}x{}", current.width(), current.height());
+                println!("fonts: {}", font::BUILTIN_FONTS.join(" "));
+            },
+        }
+    }
+    LUSHAddCommand!("font", font_handler, "font [name]", "show or switch the console font");
+
+    fn complete_font(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() > 0 {
+            return Vec::new();
+        }
+        font::BUILTIN_FONTS.iter().map(|name| String::from(*name)).collect()
+    }
+    LUSHComplete!("font", complete_font);
+
+    fn mode_handler(args: &[String]) {
+        let mode: Vec<usize> = args.get(0).map_or(Vec::new(), |arg| {
+            arg.split('x').filter_map(|n| n.parse::<usize>().ok()).collect()
+        });
+
+        let result = match mode.as_slice() {
+            [width, height] => switch_mode(*width, *height, 8),
+            [width, height, bits] => switch_mode(*width, *height, *bits),
+            _ => {
+                let framebuffer = WRITER.lock().framebuffer();
+                println!("mode: {}x{}x{}", framebuffer.width, framebuffer.height, framebuffer.bytes_per_pixel * 8);
+                return;
+            },
+        };
+
+        if let Err(e) = result {
+            color!(Color::LightRed);
+            println!("{}", e);
+        }
+    }
+    LUSHAddCommand!("mode", mode_handler, "mode [width]x[height][x<bits>]", "show or change the screen mode");
+
+    fn complete_mode(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() > 0 {
+            return Vec::new();
+        }
+        ["320x200", "640x480", "800x600", "1024x768"].iter().map(|mode| String::from(*mode)).collect()
+    }
+    LUSHComplete!("mode", complete_mode);
+
+    fn scrollback_handler(args: &[String]) {
+        if let Some(arg) = args.get(0) {
+            match arg.parse::<usize>() {
+                Ok(lines) => lure_scrollback!(lines),
+                Err(_) => {
+                    color!(Color::LightRed);
+                    println!("usage: scrollback [lines]");
+                    return;
+                },
+            }
+        }
+        // read it before printing, which locks the renderer too.
+        let lines = luna::shell::LURE.lock().scrollback;
+        println!("scrollback: {} lines", lines);
+    }
+    LUSHAddCommand!("scrollback", scrollback_handler, "scrollback [lines]", "show or change how many lines of output are kept");
+
+    fn uptime_handler(args: &[String]) {
+        let uptime = time::uptime();
+        let seconds = uptime.as_secs();
+        println!("up {}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, uptime.subsec_millis());
+    }
+    LUSHAddCommand!("uptime", uptime_handler, "uptime", "show how long the kernel has been running");
+
+    fn date_handler(args: &[String]) {
+        match (args.get(0).map(|arg| arg.as_str()), args.get(1).map(|arg| arg.as_str())) {
+            (None, _) => println!("{} UTC", rtc::now()),
+            (Some("clock"), Some("on")) => lure_clock!(true),
+            (Some("clock"), Some("off")) => lure_clock!(false),
+            _ => {
+                color!(Color::LightRed);
+                println!("usage: date [clock on|off]");
+            },
+        }
+    }
+    LUSHAddCommand!("date", date_handler, "date [clock on|off]", "show the date and time, or a clock below the output");
+
+    fn complete_date(args: &[String], _word: &str) -> Vec<String> {
+        match args.len() {
+            0 => vec![String::from("clock")],
+            1 => vec![String::from("on"), String::from("off")],
+            _ => Vec::new(),
+        }
+    }
+    LUSHComplete!("date", complete_date);
+
+    fn sleep_handler(args: &[String]) {
+        match args.get(0).and_then(|arg| arg.parse::<u64>().ok()) {
+            Some(ms) => time::sleep(Duration::from_millis(ms)),
+            None => {
+                color!(Color::LightRed);
+                println!("usage: sleep <ms>");
+            },
+        }
+    }
+    LUSHAddCommand!("sleep", sleep_handler, "sleep <ms>", "wait for a number of milliseconds");
+
+    fn vsync_handler(args: &[String]) {
+        match args.get(0).map(|arg| arg.as_str()) {
+            Some("on") => WRITER.lock().set_vsync(true),
+            Some("off") => WRITER.lock().set_vsync(false),
+            Some(_) => {
+                color!(Color::LightRed);
+                println!("vsync: expected on or off");
+            },
+            None => {
+                let writer = WRITER.lock();
+                let flipping = writer.framebuffer().flip.is_some();
+                println!("vsync: {}, page flipping: {}",
+                    if writer.vsync() { "on" } else { "off" },
+                    if flipping { "yes" } else { "no" });
+            },
+        }
+    }
+    LUSHAddCommand!("vsync", vsync_handler, "vsync [on|off]", "wait for vertical retrace when drawing");
+
+    fn complete_vsync(args: &[String], _word: &str) -> Vec<String> {
+        if args.len() > 0 {
+            return Vec::new();
+        }
+        vec![String::from("on"), String::from("off")]
+    }
+    LUSHComplete!("vsync", complete_vsync);
+
+    fn bounce_handler(args: &[String]) {
+        let fps = args.get(0).and_then(|arg| arg.parse::<usize>().ok()).unwrap_or(30);
+        let (width, height) = luna::vga_buffer::size();
+        let radius = (height / 16).max(2) as isize;
+        let (mut x, mut y) = (radius, radius);
+        let (mut dx, mut dy) = (3, 2);
+
+        lure_enabled!(false);
+        let mut limiter = time::FrameLimiter::new(fps);
+        let start = time::Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            fill_buffer!(Color::Black);
+            fill_circle!(x, y, radius, Color::Yellow);
+            vga_apply!();
 
-        let c = char_hex_vec_to_int(args) as u8;
-        
-        for y in 0..8 {
-            for x in 0..319 {
-                raw_pixel!(x, y, c);
+            x += dx;
+            y += dy;
+            if x - radius <= 0 || x + radius >= width as isize {
+                dx = -dx;
             }
+            if y - radius <= 0 || y + radius >= height as isize {
+                dy = -dy;
+            }
+            limiter.wait();
+        }
+        fill_buffer!(Color::Black);
+        lure_enabled!(true);
+    }
+    LUSHAddCommand!("bounce", bounce_handler, "bounce [fps]", "bounce a ball across the screen");
+
+    fn paint_key_handler(key: LunaKey) {
+        if key == LunaKey::Char('\u{001b}') {
+            fill_buffer!(Color::Black);
+            lush_keypop!();
+            lush_mousepop!();
+            lure_enabled!(true);
+        }
+    }
+    fn paint_mouse_handler(event: MouseEvent) {
+        let color = if event.buttons.left {
+            Color::White
+        } else if event.buttons.right {
+            Color::Black
+        } else {
+            return;
+        };
+        draw_line!(event.previous_x as isize, event.previous_y as isize, event.x as isize, event.y as isize, color);
+        vga_apply!();
+    }
+    fn paint_handler(args: &[String]) {
+        fill_buffer!(Color::Black);
+        vga_apply!();
+        lush_keypush!(paint_key_handler);
+        lush_mousepush!(paint_mouse_handler);
+        lure_enabled!(false);
+    }
+    LUSHAddCommand!("paint", paint_handler, "paint", "draw with the mouse until esc, right button erases");
+
+    fn win_handler(args: &[String]) {
+        let numbers: Vec<usize> = args.iter().skip(1).filter_map(|arg| arg.parse::<usize>().ok()).collect();
+        let id = numbers.get(0).map(|n| window::WindowId(*n));
+
+        match (args.get(0).map_or("", |arg| arg.as_str()), id) {
+            ("open", _) => {
+                let title = args.get(1).map_or("window", |arg| arg.as_str());
+                let (width, height) = match args.get(2..4).map(|size| (size[0].parse::<usize>(), size[1].parse::<usize>())) {
+                    Some((Ok(width), Ok(height))) => (width.max(1), height.max(1)),
+                    _ => (96, 64),
+                };
+
+                // cascade new windows down from the top left.
+                let count = window::WINDOWS.lock().windows().len();
+                let (screen_width, screen_height) = luna::vga_buffer::size();
+                let x = (16 + 16 * count) % screen_width.saturating_sub(width).max(1);
+                let y = (32 + 16 * count) % screen_height.saturating_sub(height).max(1);
+                let id = match window::open(title, x, y, width, height) {
+                    Some(id) => id,
+                    None => {
+                        color!(Color::LightRed);
+                        println!("window too big");
+                        return;
+                    },
+                };
+                window::draw(id, |writer| {
+                    writer.fill_buffer(Color::ALL[1 + id.0 % 15]);
+                    for (i, c) in format!("#{}", id.0).chars().enumerate() {
+                        writer.draw_char(2 + i * writer.font().width(), 2, ScreenChar::new(c, Color::White));
+                    }
+                });
+                println!("opened window {}", id.0);
+            },
+            ("close", Some(id)) => {
+                if !window::close(id) {
+                    color!(Color::LightRed);
+                    println!("no window {}", id.0);
+                }
+            },
+            ("focus", Some(id)) => window::focus(id),
+            ("move", Some(id)) if numbers.len() == 3 => window::move_to(id, numbers[1], numbers[2]),
+            ("list", _) | ("", _) => {
+                let windows = window::WINDOWS.lock();
+                for w in windows.windows().iter().rev() {
+                    let focus = if windows.focused() == Some(w.id) { "*" } else { " " };
+                    println!("{}{:>3} {:<12} {}x{} at {},{}", focus, w.id.0, w.title, w.surface.width, w.surface.height, w.x, w.y);
+                }
+            },
+            _ => {
+                color!(Color::LightRed);
+                println!("usage: win <open|close|focus|move|list> [args...]");
+            },
         }
+    }
+    LUSHAddCommand!("win", win_handler, "win <open|close|focus|move|list> [args...]", "manage windows, drag them by the title bar");
 
-        lure_bmp!(cap_bmp!(0,0,48,8));
+    fn complete_win(args: &[String], _word: &str) -> Vec<String> {
+        match args.len() {
+            0 => ["open", "close", "focus", "move", "list"].iter().map(|word| String::from(*word)).collect(),
+            1 if args[0] != "open" && args[0] != "list" => window::WINDOWS.lock().windows().iter()
+                .map(|w| format!("{}", w.id.0))
+                .collect(),
+            _ => Vec::new(),
+        }
     }
-    LUSHAddCommand!(vec!['c', 'o', 'l', 'o', 'r'], color_handler);
+    LUSHComplete!("win", complete_win);
 
-    fn edit_handler(args: Vec<char>) {
+    fn edit_handler(args: &[String]) {
     
     }
-    LUSHAddCommand!(vec!['e', 'd', 'i', 't'], edit_handler);
+    LUSHAddCommand!("edit", edit_handler, "edit", "not implemented yet");
 
 
     color!(Color::Pink);
@@ -257,9 +785,14 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("rustc 1.41.0-nightly");
 
     color!(Color::LightBlue);
-    LUSHKeyHandler!('\u{0000}');
+    LUSHKeyHandler!(LunaKey::Char('\u{0000}'));
 
-    luna::hlt_loop();
+    // from here on the shell runs as a task, fed by the interrupt handlers.
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(InputTask::new()));
+    executor.spawn(Task::new(ClockTask::new()));
+    executor.spawn(Task::new(BlinkTask::new()));
+    executor.run();
 }
 
 /// This function is called on panic.
@@ -0,0 +1,264 @@
+use crate::vga_buffer;
+use core::fmt;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// Bytes from the keyboard and mouse, and data for controller commands.
+const DATA: u16 = 0x60;
+/// Reads the controller status, and takes controller commands when written.
+const COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+/// Set along with `STATUS_OUTPUT_FULL` when the byte waiting came from the mouse.
+const STATUS_AUX_DATA: u8 = 0x20;
+
+const ENABLE_AUX: u8 = 0xA8;
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+/// Sends the next data byte to the mouse instead of the keyboard.
+const WRITE_AUX: u8 = 0xD4;
+
+/// Config bits that raise IRQ12 for mouse data and turn off the mouse clock.
+const CONFIG_AUX_IRQ: u8 = 0x02;
+const CONFIG_AUX_CLOCK_OFF: u8 = 0x20;
+
+const MOUSE_DEFAULTS: u8 = 0xF6;
+const MOUSE_ENABLE_REPORTING: u8 = 0xF4;
+const MOUSE_ACK: u8 = 0xFA;
+
+/// How many times to poll the controller before giving up on it.
+const TIMEOUT: usize = 100_000;
+
+lazy_static! {
+    static ref MOUSE: Mutex<Mouse> = Mutex::new(Mouse {
+        decoder: Decoder::new(),
+        x: 0,
+        y: 0,
+    });
+}
+
+/// Why the mouse couldn't be set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseError {
+    /// The controller stopped responding, as when there's no PS/2 controller.
+    Timeout,
+    /// The mouse didn't acknowledge a command, as when none is plugged in.
+    NoAck,
+}
+
+impl fmt::Display for MouseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MouseError::Timeout => write!(f, "ps/2 controller timed out"),
+            MouseError::NoAck => write!(f, "mouse did not respond"),
+        }
+    }
+}
+
+/// Which mouse buttons are held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// One packet from the mouse: how far it moved, with up positive, and the buttons held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub dx: i16,
+    pub dy: i16,
+    pub buttons: Buttons,
+}
+
+/// A mouse movement or button change, passed to the mouse listeners in `LULI`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Where the pointer is on screen.
+    pub x: usize,
+    pub y: usize,
+    /// Where it was before this event.
+    pub previous_x: usize,
+    pub previous_y: usize,
+    pub buttons: Buttons,
+}
+
+/// Puts the bytes from the mouse back together into packets.
+pub struct Decoder {
+    bytes: [u8; 3],
+    count: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            bytes: [0; 3],
+            count: 0,
+        }
+    }
+
+    /// Adds a byte from the mouse, returning the packet it completes.
+    pub fn add_byte(&mut self, byte: u8) -> Option<Packet> {
+        // the first byte always has bit 3 set, so a lost byte doesn't misalign every packet after it.
+        if self.count == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.bytes[self.count] = byte;
+        self.count = self.count + 1;
+        if self.count < 3 {
+            return None;
+        }
+        self.count = 0;
+
+        let [flags, x, y] = self.bytes;
+        // the 9th bit of each movement is its sign. an overflowed movement is meaningless.
+        let movement = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i16 - 256
+            } else {
+                value as i16
+            }
+        };
+        Some(Packet {
+            dx: movement(x, 0x10, 0x40),
+            dy: movement(y, 0x20, 0x80),
+            buttons: Buttons {
+                left: flags & 0x01 != 0,
+                right: flags & 0x02 != 0,
+                middle: flags & 0x04 != 0,
+            },
+        })
+    }
+}
+
+/// The decoder and where the pointer is.
+struct Mouse {
+    decoder: Decoder,
+    x: usize,
+    y: usize,
+}
+
+fn wait_for_write() -> Result<(), MouseError> {
+    let mut status: Port<u8> = Port::new(COMMAND);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+    }
+    Err(MouseError::Timeout)
+}
+
+fn read_data() -> Result<u8, MouseError> {
+    let mut status: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(DATA);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & STATUS_OUTPUT_FULL != 0 {
+            return Ok(unsafe { data.read() });
+        }
+    }
+    Err(MouseError::Timeout)
+}
+
+fn command(byte: u8) -> Result<(), MouseError> {
+    wait_for_write()?;
+    unsafe { Port::<u8>::new(COMMAND).write(byte) };
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), MouseError> {
+    wait_for_write()?;
+    unsafe { Port::<u8>::new(DATA).write(byte) };
+    Ok(())
+}
+
+fn mouse_command(byte: u8) -> Result<(), MouseError> {
+    command(WRITE_AUX)?;
+    write_data(byte)?;
+    match read_data()? {
+        MOUSE_ACK => Ok(()),
+        _ => Err(MouseError::NoAck),
+    }
+}
+
+/// Turns on the PS/2 mouse and its interrupt. Run this with interrupts
+/// disabled, or the keyboard handler would read the mouse's replies.
+pub fn init() -> Result<(), MouseError> {
+    // throw away anything left over from the BIOS.
+    let mut status: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(DATA);
+    unsafe {
+        while status.read() & STATUS_OUTPUT_FULL != 0 {
+            data.read();
+        }
+    }
+
+    command(ENABLE_AUX)?;
+    command(READ_CONFIG)?;
+    let config = read_data()?;
+    command(WRITE_CONFIG)?;
+    write_data((config | CONFIG_AUX_IRQ) & !CONFIG_AUX_CLOCK_OFF)?;
+
+    mouse_command(MOUSE_DEFAULTS)?;
+    mouse_command(MOUSE_ENABLE_REPORTING)
+}
+
+/// Reads a byte from the mouse, if one is waiting. Called by the mouse
+/// interrupt handler, which must not lock anything the shell might hold.
+pub fn receive() -> Option<u8> {
+    // a spurious interrupt would otherwise take a byte meant for the keyboard.
+    let status = unsafe { Port::<u8>::new(COMMAND).read() };
+    if status & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) != STATUS_OUTPUT_FULL | STATUS_AUX_DATA {
+        return None;
+    }
+    Some(unsafe { Port::<u8>::new(DATA).read() })
+}
+
+/// Decodes a byte from `receive`, returning the event once a whole packet
+/// has arrived.
+pub fn add_byte(byte: u8) -> Option<MouseEvent> {
+    let mut mouse = MOUSE.lock();
+    let packet = mouse.decoder.add_byte(byte)?;
+
+    // the screen may have changed size since the last packet.
+    let (width, height) = vga_buffer::size();
+    let (previous_x, previous_y) = (mouse.x, mouse.y);
+    mouse.x = (mouse.x as isize + packet.dx as isize).max(0).min(width as isize - 1) as usize;
+    mouse.y = (mouse.y as isize - packet.dy as isize).max(0).min(height as isize - 1) as usize;
+
+    Some(MouseEvent {
+        x: mouse.x,
+        y: mouse.y,
+        previous_x: previous_x,
+        previous_y: previous_y,
+        buttons: packet.buttons,
+    })
+}
+
+#[test_case]
+fn test_decode_packets() {
+    serial_print!("test_decode_packets...");
+    let mut decoder = Decoder::new();
+
+    // a stray byte without bit 3 is skipped until a packet starts.
+    assert_eq!(decoder.add_byte(0x00), None);
+    assert_eq!(decoder.add_byte(0x29), None);
+    assert_eq!(decoder.add_byte(5), None);
+    let packet = decoder.add_byte(0xfe).unwrap();
+    assert_eq!((packet.dx, packet.dy), (5, -2));
+    assert_eq!(packet.buttons, Buttons { left: true, right: false, middle: false });
+
+    // overflowed movement is dropped, but the buttons still count.
+    decoder.add_byte(0x4a);
+    decoder.add_byte(0xff);
+    let packet = decoder.add_byte(3).unwrap();
+    assert_eq!((packet.dx, packet.dy), (0, 3));
+    assert!(packet.buttons.right);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,146 @@
+use crate::vga_buffer::Palette;
+use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// Selects the DAC entry to read from.
+const DAC_READ_INDEX: u16 = 0x3C7;
+/// Selects the DAC entry to write to.
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+/// Reads or writes the red, green and blue levels of the selected entry in turn.
+const DAC_DATA: u16 = 0x3C9;
+/// Bit 3 is set while the display is in vertical retrace.
+const INPUT_STATUS: u16 = 0x3DA;
+
+lazy_static! {
+    /// The palette the BIOS set up, so it can be restored.
+    static ref DEFAULT: Palette = save();
+}
+
+/// Remembers the palette set up by the BIOS, before anything changes it.
+pub fn init() {
+    lazy_static::initialize(&DEFAULT);
+}
+
+/// The DAC keeps six bits per channel, so the top six bits of each level are used.
+fn to_dac(level: u8) -> u8 {
+    level >> 2
+}
+
+fn from_dac(level: u8) -> u8 {
+    let level = level & 0x3f;
+    (level << 2) | (level >> 4)
+}
+
+/// Reads the red, green and blue levels of one palette entry.
+pub fn read(index: u8) -> [u8; 3] {
+    let mut select: Port<u8> = Port::new(DAC_READ_INDEX);
+    let mut data: Port<u8> = Port::new(DAC_DATA);
+
+    unsafe {
+        select.write(index);
+        [from_dac(data.read()), from_dac(data.read()), from_dac(data.read())]
+    }
+}
+
+/// Sets the red, green and blue levels of one palette entry.
+pub fn write(index: u8, rgb: [u8; 3]) {
+    let mut select: Port<u8> = Port::new(DAC_WRITE_INDEX);
+    let mut data: Port<u8> = Port::new(DAC_DATA);
+
+    unsafe {
+        select.write(index);
+        for level in rgb.iter() {
+            data.write(to_dac(*level));
+        }
+    }
+}
+
+/// Reads the whole palette.
+pub fn save() -> Palette {
+    let mut palette = Palette::new();
+    for i in 0..256 {
+        palette.colors[i] = read(i as u8);
+    }
+    palette
+}
+
+/// Writes the whole palette. The DAC moves to the next entry after each one.
+pub fn load(palette: &Palette) {
+    let mut select: Port<u8> = Port::new(DAC_WRITE_INDEX);
+    let mut data: Port<u8> = Port::new(DAC_DATA);
+
+    unsafe {
+        select.write(0);
+        for rgb in palette.colors.iter() {
+            for level in rgb.iter() {
+                data.write(to_dac(*level));
+            }
+        }
+    }
+}
+
+/// Restores the palette the BIOS set up.
+pub fn reset() {
+    load(&DEFAULT);
+}
+
+/// Waits for the start of the next vertical retrace, when the palette can change without tearing.
+pub fn wait_for_retrace() {
+    let mut status: Port<u8> = Port::new(INPUT_STATUS);
+
+    unsafe {
+        while status.read() & 0x08 != 0 {}
+        while status.read() & 0x08 == 0 {}
+    }
+}
+
+/// Blends from one palette to another over `frames` retraces.
+pub fn fade(from: &Palette, to: &Palette, frames: usize) {
+    let mut blended = Palette::new();
+    for frame in 1..=frames {
+        for i in 0..256 {
+            for c in 0..3 {
+                let (a, b) = (from.colors[i][c] as usize, to.colors[i][c] as usize);
+                blended.colors[i][c] = ((a * (frames - frame) + b * frame) / frames) as u8;
+            }
+        }
+        wait_for_retrace();
+        load(&blended);
+    }
+}
+
+/// Fades the current palette to black.
+pub fn fade_out(frames: usize) {
+    fade(&save(), &Palette::new(), frames);
+}
+
+/// Fades from black to the given palette.
+pub fn fade_in(palette: &Palette, frames: usize) {
+    fade(&Palette::new(), palette, frames);
+}
+
+/// Rotates the entries from `first` to `last` inclusive by one place.
+pub fn cycle(first: u8, last: u8) {
+    if first >= last {
+        return;
+    }
+
+    let wrapped = read(last);
+    for i in (first..last).rev() {
+        write(i + 1, read(i));
+    }
+    write(first, wrapped);
+}
+
+#[test_case]
+fn test_write_and_read_entry() {
+    serial_print!("test_write_and_read_entry...");
+    let saved = read(200);
+    write(200, [255, 0, 130]);
+    assert_eq!(read(200), [255, 0, 130]);
+    write(200, saved);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,67 @@
+use x86_64::instructions::port::Port;
+
+/// Selects the configuration register `CONFIG_DATA` reads.
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// A function of a device on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub bus: u8,
+    pub slot: u8,
+    pub function: u8,
+}
+
+impl Device {
+    /// Reads a 32-bit register from the device's configuration space.
+    pub fn read(&self, offset: u8) -> u32 {
+        let address = 0x8000_0000
+            | (self.bus as u32) << 16
+            | (self.slot as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xfc) as u32;
+
+        let mut select: Port<u32> = Port::new(CONFIG_ADDRESS);
+        let mut data: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe {
+            select.write(address);
+            data.read()
+        }
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(0x00) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(0x00) >> 16) as u16
+    }
+
+    /// The address a memory base address register points to, without its flag bits.
+    pub fn bar(&self, index: u8) -> u32 {
+        self.read(0x10 + 4 * index) & !0x0f
+    }
+}
+
+/// Finds the first function with the given vendor and device IDs.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<Device> {
+    for bus in 0..=255 {
+        for slot in 0..32 {
+            for function in 0..8 {
+                let device = Device { bus: bus, slot: slot, function: function };
+                let vendor = device.vendor_id();
+                // nothing is plugged in here.
+                if vendor == 0xffff {
+                    if function == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                if vendor == vendor_id && device.device_id() == device_id {
+                    return Some(device);
+                }
+            }
+        }
+    }
+    None
+}
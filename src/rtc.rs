@@ -0,0 +1,159 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// Selects the CMOS register `DATA` reads.
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Set in status register A while the clock is changing its registers.
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Status register B flags for a 24-hour clock and for binary rather than BCD values.
+const HOURS_24: u8 = 0x02;
+const BINARY: u8 = 0x04;
+/// Set in the hours register for PM on a 12-hour clock.
+const PM: u8 = 0x80;
+
+/// How many times to wait out an update, or read again, before settling for the last read.
+const MAX_RETRIES: usize = 10_000;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// A date and time as kept by the real-time clock, which is usually UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Decodes the clock registers, in the order seconds, minutes, hours,
+    /// day, month and year, using the format flags from status register B.
+    pub fn from_registers(registers: [u8; 6], status_b: u8) -> DateTime {
+        let decode = |value: u8| {
+            if status_b & BINARY != 0 {
+                value
+            } else {
+                (value >> 4) * 10 + (value & 0x0f)
+            }
+        };
+
+        let [seconds, minutes, hours, day, month, year] = registers;
+        let mut hour = decode(hours & !PM);
+        if status_b & HOURS_24 == 0 {
+            // 12 AM is midnight and 12 PM is noon.
+            hour = hour % 12 + if hours & PM != 0 { 12 } else { 0 };
+        }
+
+        DateTime {
+            // the century register isn't always there, so assume this one.
+            year: 2000 + decode(year) as u16,
+            month: decode(month),
+            day: decode(day),
+            hour: hour,
+            minute: decode(minutes),
+            second: decode(seconds),
+        }
+    }
+
+    /// Days since 1970-01-01.
+    pub fn days_since_epoch(&self) -> i64 {
+        // from Howard Hinnant's days_from_civil, with years starting in March.
+        let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let era = if year >= 0 { year } else { year - 399 } / 400;
+        let year_of_era = year - era * 400;
+        let month = self.month as i64;
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    /// The day of the week, from 0 for Monday to 6 for Sunday.
+    pub fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday.
+        (self.days_since_epoch() + 3).rem_euclid(7) as usize
+    }
+
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn timestamp(&self) -> i64 {
+        self.days_since_epoch() * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            WEEKDAYS[self.weekday()], self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut index: Port<u8> = Port::new(INDEX);
+    let mut data: Port<u8> = Port::new(DATA);
+    unsafe {
+        index.write(register);
+        data.read()
+    }
+}
+
+fn read_registers() -> [u8; 6] {
+    // without a CMOS the port floats high and always looks mid-update.
+    for _ in 0..MAX_RETRIES {
+        if read_register(REG_STATUS_A) & UPDATE_IN_PROGRESS == 0 {
+            break;
+        }
+    }
+    [
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+    ]
+}
+
+/// Reads the current date and time from the CMOS real-time clock.
+pub fn now() -> DateTime {
+    // an update can still start partway through, so read until two reads agree.
+    // if they never do, the last read is as good as any.
+    let mut registers = read_registers();
+    for _ in 0..MAX_RETRIES {
+        let again = read_registers();
+        if again == registers {
+            break;
+        }
+        registers = again;
+    }
+    DateTime::from_registers(registers, read_register(REG_STATUS_B))
+}
+
+#[test_case]
+fn test_decode_date_time() {
+    serial_print!("test_decode_date_time...");
+    // BCD on a 12-hour clock: 11:59:30 PM.
+    let date = DateTime::from_registers([0x30, 0x59, 0x91, 0x18, 0x10, 0x26], 0);
+    assert_eq!(date, DateTime { year: 2026, month: 10, day: 18, hour: 23, minute: 59, second: 30 });
+    assert_eq!(date.weekday(), 6);
+
+    // binary on a 24-hour clock.
+    let date = DateTime::from_registers([0, 0, 0, 1, 1, 0], BINARY | HOURS_24);
+    assert_eq!(date.timestamp(), 946_684_800);
+    assert_eq!(WEEKDAYS[date.weekday()], "Sat");
+    serial_println!("[ok]");
+}
@@ -1,15 +1,76 @@
+use crate::{ansi::Action, shell::LunaKey};
 use lazy_static::lazy_static;
+use pc_keyboard::KeyCode;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+/// The I/O port of the first serial port.
+const COM1: u16 = 0x3F8;
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
         Mutex::new(serial_port)
     };
 }
 
+/// Initializes the serial port, which also enables its receive interrupt.
+pub fn init() {
+    lazy_static::initialize(&SERIAL1);
+}
+
+/// Reads a byte received on the serial port, if there is one.
+pub fn receive() -> Option<u8> {
+    let mut line_status: Port<u8> = Port::new(COM1 + 5);
+    let mut data: Port<u8> = Port::new(COM1);
+
+    unsafe {
+        if line_status.read() & 1 == 0 {
+            return None;
+        }
+        Some(data.read())
+    }
+}
+
+/// Translates input from a terminal on the serial port into a key for the shell.
+pub fn decode_key(action: Action) -> Option<LunaKey> {
+    let key = match action {
+        Action::Print('\r') => LunaKey::Char('\n'),
+        Action::Print('\u{007f}') => LunaKey::Char('\u{0008}'),
+        Action::Print(c) => LunaKey::Char(c),
+        Action::Csi(params, action) => {
+            let code = match action {
+                'A' => KeyCode::ArrowUp,
+                'B' => KeyCode::ArrowDown,
+                'C' => KeyCode::ArrowRight,
+                'D' => KeyCode::ArrowLeft,
+                'H' => KeyCode::Home,
+                'F' => KeyCode::End,
+                '~' => match params.get(0, 0) {
+                    1 | 7 => KeyCode::Home,
+                    3 => return Some(LunaKey::Char('\u{007f}')),
+                    4 | 8 => KeyCode::End,
+                    5 => KeyCode::PageUp,
+                    6 => KeyCode::PageDown,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+
+            // xterm sends modifiers as the second parameter, where 2 is shift.
+            if params.get(1, 1) == 2 {
+                LunaKey::Shifted(code)
+            } else {
+                LunaKey::Raw(code)
+            }
+        },
+        _ => return None,
+    };
+    Some(key)
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
@@ -1,37 +1,55 @@
 use crate::{
+    ansi::{self, Action, Params},
+    display::{Backend, Display},
+    mouse::MouseEvent,
+    rtc,
+    task::{timer::{self, Interval}, Stream},
     print,
     println,
-    draw_char,
-    fill_buffer,
-    bmp,
-    cap_bmp,
     rect,
-    vga_apply,
     color,
-    vga_buffer::Bitmap,
+    vga_buffer::{self, Bitmap},
     vga_buffer::Color,
-    vga_buffer::ScreenChar
+    vga_buffer::ScreenChar,
+    vga_buffer::Attributes,
 };
-use alloc::{vec::Vec,vec};
+use alloc::{collections::{BTreeMap, VecDeque}, format, string::String, vec::Vec, vec};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use pc_keyboard::KeyCode;
 
 lazy_static! {
-    pub static ref LUSH: Mutex<LunaShell> = Mutex::new(LunaShell {
-        input: Vec::new(),
-        commands: Vec::new(),
-        handlers: Vec::new(),
-    });
+    pub static ref LUSH: Mutex<LunaShell> = Mutex::new(LunaShell::new());
 }
 
 lazy_static! {
     pub static ref LURE: Mutex<LunaRenderer> = Mutex::new(LunaRenderer {
         input: Vec::new(),
+        cursor: 0,
         lastInputLength: 65535,
-        lines: Vec::new(),
+        lines: {
+            let mut lines = VecDeque::new();
+            lines.push_back(LunaLine::new());
+            lines
+        },
+        scrollback: SCROLLBACK_LINES,
+        scroll: 0,
+        line: 0,
+        column: 0,
+        saved_cursor: (0, 0),
+        parser: ansi::Parser::new(),
+        bold: false,
         color: Color::LightGray,
+        background: Color::Black,
+        attributes: Attributes::default(),
         enabled: true,
+        clock: false,
+        display: Backend::new(),
     });
 }
 
@@ -39,22 +57,204 @@ lazy_static! {
 lazy_static! {
     pub static ref LULI: Mutex<LunaListeners> = Mutex::new(LunaListeners {
         key_listeners: Vec::new(),
+        mouse_listeners: Vec::new(),
     });
 }
 
 pub struct LunaListeners {
-    pub key_listeners: Vec<fn(char)>
+    pub key_listeners: Vec<fn(LunaKey)>,
+    /// Like the key listeners, only the most recently pushed one hears the mouse.
+    pub mouse_listeners: Vec<fn(MouseEvent)>,
+}
+
+/// A key press passed from the keyboard interrupt to the shell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LunaKey {
+    /// A key that produces a character.
+    Char(char),
+    /// A key without a character, such as the arrow keys.
+    Raw(KeyCode),
+    /// A key without a character pressed while shift was held.
+    Shifted(KeyCode),
+}
+
+/// The number of lines kept in the shell history.
+const HISTORY_SIZE: usize = 32;
+
+/// The function that runs a command.
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// A command registered through `LUSHAddCommand!`.
+    Extern(fn(&[String])),
+    /// A command built into the shell, which gets access to the shell itself.
+    Builtin(fn(&mut LunaShell, &[String])),
+}
+
+/// A command that can be run from the shell, along with its help text.
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub handler: Handler,
+    /// Suggests completions for an argument, given the arguments before it
+    /// and the partial word being completed.
+    pub complete: Option<fn(&[String], &str) -> Vec<String>>,
 }
 
 pub struct LunaShell {
     pub input: Vec<char>,
-    pub commands: Vec<Vec<char>>,
-    pub handlers: Vec<fn(Vec<char>)>
+    /// The position in `input` where typed characters are inserted.
+    pub cursor: usize,
+    pub commands: BTreeMap<&'static str, Command>,
+    pub aliases: BTreeMap<&'static str, &'static str>,
+    pub history: VecDeque<String>,
+    /// The history entry being shown, or `None` while editing a new line.
+    history_index: Option<usize>,
+    /// The line that was being edited before browsing the history.
+    history_draft: Vec<char>,
+}
+
+/// An error produced while splitting a command line into arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenizeError {
+    /// A quote was opened but never closed.
+    UnterminatedQuote(char),
+    /// The line ended with a backslash that escapes nothing.
+    TrailingEscape,
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenizeError::UnterminatedQuote(quote) => write!(f, "Unterminated {} quote.", quote),
+            TokenizeError::TrailingEscape => write!(f, "Trailing backslash."),
+        }
+    }
+}
+
+/// Splits a command line into its arguments.
+///
+/// Arguments are separated by any amount of whitespace. Double quotes group
+/// whitespace into a single argument and allow `\"` and `\\` escapes, single
+/// quotes are taken literally, and a backslash outside of quotes escapes the
+/// character following it.
+pub fn tokenize(input: &str) -> Result<Vec<String>, TokenizeError> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // an argument can be empty (`""`), so track whether one was started.
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some('\'') => {
+                if c == '\'' {
+                    quote = None;
+                } else {
+                    current.push(c);
+                }
+            },
+            Some(_) => match c {
+                '"' => quote = None,
+                '\\' => match chars.next() {
+                    Some(e @ '"') | Some(e @ '\\') => current.push(e),
+                    Some(e) => {
+                        current.push('\\');
+                        current.push(e);
+                    },
+                    None => return Err(TokenizeError::UnterminatedQuote('"')),
+                },
+                _ => current.push(c),
+            },
+            None => match c {
+                '"' | '\'' => {
+                    quote = Some(c);
+                    in_token = true;
+                },
+                '\\' => match chars.next() {
+                    Some(e) => {
+                        current.push(e);
+                        in_token = true;
+                    },
+                    None => return Err(TokenizeError::TrailingEscape),
+                },
+                _ if c.is_whitespace() => {
+                    if in_token {
+                        args.push(core::mem::replace(&mut current, String::new()));
+                        in_token = false;
+                    }
+                },
+                _ => {
+                    current.push(c);
+                    in_token = true;
+                },
+            },
+        }
+    }
+
+    if let Some(quote) = quote {
+        return Err(TokenizeError::UnterminatedQuote(quote));
+    }
+
+    if in_token {
+        args.push(current);
+    }
+
+    Ok(args)
 }
 
 impl LunaShell {
-    pub fn keyboard_event(&mut self, key: char) {
-        if key == '\u{0000}' {
+    pub fn new() -> LunaShell {
+        let mut shell = LunaShell {
+            input: Vec::new(),
+            cursor: 0,
+            commands: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            history: VecDeque::new(),
+            history_index: None,
+            history_draft: Vec::new(),
+        };
+
+        shell.add_command(Command {
+            name: "help",
+            aliases: &["?"],
+            usage: "help [command]",
+            summary: "list commands or describe one",
+            handler: Handler::Builtin(help_command),
+            complete: None,
+        });
+
+        shell.add_command(Command {
+            name: "history",
+            aliases: &[],
+            usage: "history",
+            summary: "list previous command lines",
+            handler: Handler::Builtin(history_command),
+            complete: None,
+        });
+
+        return shell;
+    }
+
+    /// Registers a command, replacing any earlier command with the same name.
+    pub fn add_command(&mut self, command: Command) {
+        for alias in command.aliases {
+            self.aliases.insert(alias, command.name);
+        }
+        self.commands.insert(command.name, command);
+    }
+
+    /// Looks up a command by its name or one of its aliases.
+    pub fn command(&self, name: &str) -> Option<&Command> {
+        let name = self.aliases.get(name).copied().unwrap_or(name);
+        self.commands.get(name)
+    }
+
+    pub fn keyboard_event(&mut self, key: LunaKey) {
+        if key == LunaKey::Char('\u{0000}') {
             LURE.lock().draw();
             return;
         }
@@ -67,195 +267,667 @@ impl LunaShell {
             return;
         }
 
-        if key == '\n' {
-            self.finish_line();
-            return;
+        match key {
+            LunaKey::Raw(KeyCode::PageUp) => {
+                let mut lure = LURE.lock();
+                let page = lure.view_lines() - 1;
+                lure.scroll_up(page);
+                return;
+            },
+            LunaKey::Raw(KeyCode::PageDown) => {
+                let mut lure = LURE.lock();
+                let page = lure.view_lines() - 1;
+                lure.scroll_down(page);
+                return;
+            },
+            LunaKey::Shifted(KeyCode::ArrowUp) => {
+                LURE.lock().scroll_up(1);
+                return;
+            },
+            LunaKey::Shifted(KeyCode::ArrowDown) => {
+                LURE.lock().scroll_down(1);
+                return;
+            },
+            LunaKey::Shifted(code) => {
+                self.keyboard_event(LunaKey::Raw(code));
+                return;
+            },
+            LunaKey::Char('\n') => {
+                self.finish_line();
+                return;
+            },
+            LunaKey::Char('\t') => self.complete(),
+            LunaKey::Char('\u{0008}') => {
+                if self.cursor > 0 {
+                    self.cursor = self.cursor - 1;
+                    self.input.remove(self.cursor);
+                }
+            },
+            LunaKey::Char('\u{007f}') => {
+                if self.cursor < self.input.len() {
+                    self.input.remove(self.cursor);
+                }
+            },
+            LunaKey::Char(c) if !c.is_control() => {
+                self.input.insert(self.cursor, c);
+                self.cursor = self.cursor + 1;
+            },
+            LunaKey::Raw(KeyCode::ArrowLeft) => {
+                if self.cursor > 0 {
+                    self.cursor = self.cursor - 1;
+                }
+            },
+            LunaKey::Raw(KeyCode::ArrowRight) => {
+                if self.cursor < self.input.len() {
+                    self.cursor = self.cursor + 1;
+                }
+            },
+            LunaKey::Raw(KeyCode::Home) => self.cursor = 0,
+            LunaKey::Raw(KeyCode::End) => self.cursor = self.input.len(),
+            LunaKey::Raw(KeyCode::ArrowUp) => self.history_up(),
+            LunaKey::Raw(KeyCode::ArrowDown) => self.history_down(),
+            _ => {},
+        }
+
+        self.update_input();
+    }
+
+    /// Hands the input line and cursor to the renderer and redraws it,
+    /// scrolling back down to the newest output.
+    fn update_input(&self) {
+        let mut lure = LURE.lock();
+        lure.input = self.input.to_vec();
+        lure.cursor = self.cursor;
+        if lure.scroll > 0 {
+            // force the output to be redrawn at the bottom.
+            lure.scroll = 0;
+            lure.lastInputLength = 65535;
+        }
+        lure.draw();
+    }
+
+    /// Replaces the input with the previous history entry.
+    fn history_up(&mut self) {
+        let index = match self.history_index {
+            None if self.history.len() > 0 => {
+                self.history_draft = self.input.to_vec();
+                self.history.len() - 1
+            },
+            Some(index) if index > 0 => index - 1,
+            _ => return,
+        };
+
+        self.history_index = Some(index);
+        self.input = self.history[index].chars().collect();
+        self.cursor = self.input.len();
+    }
+
+    /// Replaces the input with the next history entry, or the line that was
+    /// being edited once the end of the history is reached.
+    fn history_down(&mut self) {
+        match self.history_index {
+            Some(index) if index + 1 < self.history.len() => {
+                self.history_index = Some(index + 1);
+                self.input = self.history[index + 1].chars().collect();
+            },
+            Some(_) => {
+                self.history_index = None;
+                self.input = core::mem::replace(&mut self.history_draft, Vec::new());
+            },
+            None => {},
         }
+        self.cursor = self.input.len();
+    }
+
+    /// Completes the word before the cursor, listing the candidates when
+    /// more than one matches.
+    fn complete(&mut self) {
+        let word_start = self.input[..self.cursor].iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        let before: String = self.input[..word_start].iter().collect();
+        let word: String = self.input[word_start..self.cursor].iter().collect();
+
+        let args = match tokenize(&before) {
+            Ok(args) => args,
+            Err(_) => return,
+        };
 
-        if key == '\u{0008}' {
-            if self.input.len() > 0 {
-                self.input.swap_remove(self.input.len() - 1);
-                LURE.lock().input = self.input.to_vec();
+        let mut candidates: Vec<String> = if args.len() == 0 {
+            self.commands.keys().chain(self.aliases.keys())
+                .map(|name| String::from(*name))
+                .collect()
+        } else {
+            match self.command(&args[0]).and_then(|command| command.complete) {
+                Some(complete) => complete(&args[1..], &word),
+                None => Vec::new(),
             }
-            LURE.lock().draw();
+        };
+        candidates.retain(|candidate| candidate.starts_with(word.as_str()));
+        candidates.sort();
+        candidates.dedup();
+
+        if candidates.len() == 0 {
             return;
         }
 
-        self.input.push(key);
-        LURE.lock().input = self.input.to_vec();
-        LURE.lock().draw();
+        let mut completion = common_prefix(&candidates);
+        if candidates.len() == 1 {
+            completion.push(' ');
+        } else if completion.len() == word.len() {
+            color!(Color::DarkGray);
+            print!("{}\n", candidates.join("  "));
+        }
+
+        for c in completion.chars().skip(word.chars().count()) {
+            self.input.insert(self.cursor, c);
+            self.cursor = self.cursor + 1;
+        }
+    }
+
+    fn push_history(&mut self, line: &str) {
+        if line.trim().len() == 0 || self.history.back().map(|last| last.as_str()) == Some(line) {
+            return;
+        }
+
+        if self.history.len() >= HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(String::from(line));
     }
 
     fn finish_line(&mut self) {
-        let input: Vec<char> = self.input.to_vec();
-        let mut command: Vec<char> = Vec::new();
+        let line: String = self.input.iter().collect();
 
         print!("\n");
         color!(Color::Blue);
         print!(">");
         color!(Color::LightGray);
-        for i in &input {
-            print!("{}", i);
-        }
+        print!("{}", line);
         color!(Color::DarkGray);
         print!("\n");
 
-        let mut cmd_idx = 0;
-
-        if input.len() > 0 {
-            for cmd in &self.commands {
-                let mut accepted = true;
+        self.history_index = None;
+        self.push_history(&line);
 
-                let mut cmd_char_idx = 0;
-
-                for i in &input {
-                    if input.len() < cmd.len() {
-                        accepted = false;
-                        break;
-                    }
+        match tokenize(&line) {
+            Ok(argv) => {
+                if argv.len() > 0 {
+                    let command = self.command(&argv[0]).copied();
 
-                    if cmd_char_idx >= cmd.len() {
-                        if *i == ' ' {
-                            break;
+                    match command.map(|command| command.handler) {
+                        Some(Handler::Extern(handler)) => handler(&argv[1..]),
+                        Some(Handler::Builtin(handler)) => handler(self, &argv[1..]),
+                        None => {
+                            color!(Color::LightRed);
+                            print!("Unknown Command.");
                         }
-                        accepted = false;
-                        break;
                     }
+                }
+            },
+            Err(err) => {
+                color!(Color::LightRed);
+                print!("{}", err);
+            }
+        }
 
-                    if &cmd[cmd_char_idx] != i {
-                        accepted = false;
-                    }
+        self.input = Vec::new();
+        self.cursor = 0;
+        
+        print!("\n");
+        self.update_input();
+    }
+}
 
-                    cmd_char_idx = cmd_char_idx + 1;
-                }
+/// Returns the longest prefix shared by all of the given words.
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix: Vec<char> = words[0].chars().collect();
 
-                if accepted {
-                    command = cmd.to_vec();
-                    break;
-                } else {
-                    cmd_idx = cmd_idx + 1;
+    for word in &words[1..] {
+        let len = prefix.iter().zip(word.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(len);
+    }
+
+    prefix.into_iter().collect()
+}
+
+fn help_command(shell: &mut LunaShell, args: &[String]) {
+    if let Some(name) = args.get(0) {
+        match shell.command(name) {
+            Some(command) => {
+                color!(Color::LightBlue);
+                println!("{}", command.usage);
+                color!(Color::LightGray);
+                print!("{}", command.summary);
+                if command.aliases.len() > 0 {
+                    color!(Color::DarkGray);
+                    print!("\naliases: {}", command.aliases.join(", "));
                 }
+            },
+            None => {
+                color!(Color::LightRed);
+                print!("No command named {}.", name);
             }
         }
+        return;
+    }
 
-        if command.len() == 0 {
-            color!(Color::LightRed);
-            print!("Unknown Command.")
-        } else {
-            // clone args to a mutable vector.
-            let mut argsstr = self.input.to_vec();
+    let mut first = true;
+    for command in shell.commands.values() {
+        if !first {
+            print!("\n");
+        }
+        first = false;
 
-            // remove "command " from string.
-            if argsstr.len() > command.len() {
-                for _ in 0..(command.len() + 1) {
-                    argsstr.remove(0);
-                }
-            } else {
-                argsstr = Vec::new();
-            }
+        color!(Color::LightBlue);
+        print!("{:<8}", command.name);
+        color!(Color::LightGray);
+        print!("{}", command.summary);
+    }
+}
 
-            self.handlers[cmd_idx](argsstr);
+fn history_command(shell: &mut LunaShell, _args: &[String]) {
+    for (i, line) in shell.history.iter().enumerate() {
+        if i > 0 {
+            print!("\n");
         }
 
-        core::mem::drop(command);
-        core::mem::drop(input);
-        
-        self.input = Vec::new();
-        LURE.lock().input = Vec::new();
-        
-        print!("\n");
-        LURE.lock().draw();
+        color!(Color::DarkGray);
+        print!("{:>3} ", i + 1);
+        color!(Color::LightGray);
+        print!("{}", line);
     }
 }
 
+/// A logical line of output, which may wrap onto several rows of the screen.
 pub struct LunaLine {
     pub chars: Vec<ScreenChar>,
-    pub bitmap: Bitmap
+    pub bitmap: Bitmap,
+    /// The rendered rows, kept while the line is on screen.
+    cache: Vec<Option<Bitmap>>,
+    /// The column and row counts of the screen the cached rows were drawn on.
+    cache_layout: (usize, usize),
 }
 
 impl LunaLine {
     pub fn new() -> LunaLine {
         LunaLine {
             chars: Vec::new(),
-            bitmap: Bitmap::new(0,0,vec![])
+            bitmap: Bitmap::new(0,0,vec![]),
+            cache: Vec::new(),
+            cache_layout: (0, 0),
         }
     }
+
+    /// Removes the line's text and bitmap.
+    pub fn clear(&mut self) {
+        self.chars.clear();
+        self.bitmap = Bitmap::new(0,0,vec![]);
+        self.cache = Vec::new();
+    }
+
+    /// The number of screen rows the line takes up when wrapped at `columns`.
+    pub fn rows(&self, columns: usize) -> usize {
+        if self.bitmap.width > 0 || self.chars.len() == 0 {
+            return 1;
+        }
+        let columns = columns.max(1);
+        (self.chars.len() + columns - 1) / columns
+    }
 }
 
+/// The number of lines kept by the renderer unless configured otherwise.
+pub const SCROLLBACK_LINES: usize = 500;
+
 pub struct LunaRenderer {
     pub input: Vec<char>,
+    pub cursor: usize,
     pub lastInputLength: u16,
-    pub lines: Vec<LunaLine>,
+    pub lines: VecDeque<LunaLine>,
+    /// The maximum number of lines kept in `lines`.
+    pub scrollback: usize,
+    /// How many rows the view is scrolled up from the bottom.
+    pub scroll: usize,
+    /// The line in `lines` that output is written to.
+    line: usize,
+    /// The position in the current line that output is written to.
+    column: usize,
+    /// The write position saved by an escape sequence.
+    saved_cursor: (usize, usize),
+    parser: ansi::Parser,
+    /// Whether the ANSI colors set next are the bright variants.
+    bold: bool,
     pub color: Color,
-    pub enabled: bool
+    pub background: Color,
+    pub attributes: Attributes,
+    pub enabled: bool,
+    /// Whether the time is shown at the right of the row below the output.
+    pub clock: bool,
+    /// What the console is drawn on.
+    display: Backend,
 }
 
 
 impl LunaRenderer {
     pub fn draw(&mut self) {
         if !self.enabled { return; }
-        let mut lIx = 0;
+        let (columns, view_lines) = (self.display.columns(), self.display.view_lines());
         let mut cIx = 0;
 
         if self.input.len() as u16 <= self.lastInputLength {
-            fill_buffer!(Color::Black);
+            self.display.clear();
 
-            while self.lines.len() > 21 {
-                self.lines.remove(0);
+            // the scroll position and view are counted in wrapped rows.
+            let total_rows: usize = self.lines.iter().map(|line| line.rows(columns)).sum();
+            let max_scroll = total_rows.saturating_sub(view_lines);
+            if self.scroll > max_scroll {
+                self.scroll = max_scroll;
             }
+            let end = total_rows - self.scroll;
+            let first = end.saturating_sub(view_lines);
 
-            for i in 0..self.lines.len() {
-                let line = &self.lines[i]; 
-                if line.bitmap.width > 0 {
-                    bmp!(0, 8*lIx, &line.bitmap);
-                } else {
-                    for chr in line.chars.to_vec() {
-                        draw_char!(8*cIx, 8*lIx, chr);
-                        cIx = cIx + 1;
+            let mut row = 0;
+            for line in self.lines.iter_mut() {
+                let rows = line.rows(columns);
+
+                if row + rows <= first || row >= end || line.cache_layout != (columns, view_lines) {
+                    // only lines on screen keep their rendered rows.
+                    line.cache = Vec::new();
+                    line.cache_layout = (columns, view_lines);
+                }
+
+                for r in 0..rows {
+                    let display_row = row + r;
+                    if display_row < first || display_row >= end {
+                        continue;
+                    }
+                    let screen_row = display_row - first;
+
+                    if line.bitmap.width > 0 {
+                        self.display.draw_bitmap(screen_row, &line.bitmap);
+                        continue;
+                    }
+
+                    if line.cache.len() < rows {
+                        line.cache.resize_with(rows, || None);
+                    }
+
+                    if let Some(cache) = &line.cache[r] {
+                        self.display.draw_bitmap(screen_row, cache);
+                    } else {
+                        for (c, &chr) in line.chars.iter().skip(r * columns).take(columns).enumerate() {
+                            self.display.draw_char(c, screen_row, chr);
+                        }
+                        line.cache[r] = self.display.capture_row(screen_row);
+                    }
+                }
+
+                row = row + rows;
+            }
+
+            if self.scroll > 0 {
+                let marker = format!("-- {} more --", self.scroll);
+                for (i, chr) in marker.chars().enumerate() {
+                    self.display.draw_char(i, view_lines, ScreenChar::new(chr, Color::Yellow));
+                }
+            }
+
+            // `ClockTask` redraws the console when the minute changes.
+            if self.clock {
+                let now = rtc::now();
+                let clock = format!("{:02}:{:02}", now.hour, now.minute);
+                // too narrow a screen has no room for it.
+                if let Some(start) = columns.checked_sub(clock.len()) {
+                    for (i, chr) in clock.chars().enumerate() {
+                        self.display.draw_char(start + i, view_lines, ScreenChar::new(chr, Color::DarkGray));
                     }
-                    self.lines[i].bitmap = cap_bmp!(0, 8*lIx, 319, 8);
                 }
-                
-                lIx = lIx + 1;
-                cIx = 0;
             }
         }
 
-        draw_char!(8*cIx, 8*24 - 4, ScreenChar::new('>', Color::LightBlue));
+        self.display.draw_input_char(cIx, ScreenChar::new('>', Color::LightBlue));
 
-        for chr in self.input.to_vec() {
+        // scroll long input sideways so the cursor stays on screen.
+        let columns = columns.saturating_sub(1).max(1);
+        let start = if self.cursor >= columns { self.cursor + 1 - columns } else { 0 };
+
+        for &chr in self.input.iter().skip(start).take(columns) {
             cIx = cIx + 1;
-            draw_char!(8*cIx, 8*24 - 4, ScreenChar::new(chr, Color::LightGray));
+            self.display.draw_input_char(cIx, ScreenChar::new(chr, Color::LightGray));
         }
 
+        let under_cursor = self.input.get(self.cursor).copied().unwrap_or(' ');
+        let cursor = ScreenChar::new(under_cursor, Color::LightGray)
+            .with_attributes(Attributes { inverse: true, ..Attributes::default() });
+        self.display.draw_input_char(self.cursor - start + 1, cursor);
+
         self.lastInputLength = self.input.len() as u16;
 
-        vga_apply!();
+        self.display.present();
+    }
+
+    /// The number of characters that fit on one row of the screen.
+    pub fn columns(&self) -> usize {
+        self.display.columns()
+    }
+
+    /// The number of output rows shown, leaving space for the scroll marker and the input line.
+    pub fn view_lines(&self) -> usize {
+        self.display.view_lines()
+    }
+
+    /// Moves the view up by the given number of lines, into the scrollback.
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll = self.scroll + lines;
+        self.draw();
+    }
+
+    /// Moves the view down by the given number of lines, towards the newest output.
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+        self.draw();
+    }
+
+    /// Forgets the rendered rows of lines with blinking characters, so the
+    /// next full redraw shows the current blink phase. Returns whether there were any.
+    fn forget_blinking(&mut self) -> bool {
+        let mut any = false;
+        for line in self.lines.iter_mut() {
+            if line.chars.iter().any(|chr| chr.attributes().blink) {
+                line.cache = Vec::new();
+                any = true;
+            }
+        }
+        any
+    }
+
+    /// Appends a line, dropping the oldest lines once `scrollback` is exceeded.
+    fn push_line(&mut self, line: LunaLine) {
+        self.lines.push_back(line);
+        self.trim_scrollback();
+    }
+
+    fn trim_scrollback(&mut self) {
+        while self.lines.len() > self.scrollback.max(1) {
+            self.lines.pop_front();
+            self.line = self.line.saturating_sub(1);
+            self.saved_cursor.0 = self.saved_cursor.0.saturating_sub(1);
+        }
+    }
+
+    /// Sets how many lines are kept, never fewer than fill the screen.
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.scrollback = lines.max(self.view_lines());
+        self.trim_scrollback();
+        self.lastInputLength = 65535;
     }
 
     fn push_bmp_line(&mut self, bmp: Bitmap) {
-        let lureLine = LunaLine {
-            bitmap: bmp,
-            chars: vec![]
-        };
-        self.lines.push(lureLine);
+        let mut lureLine = LunaLine::new();
+        lureLine.bitmap = bmp;
+        self.push_line(lureLine);
+        self.line = self.lines.len() - 1;
+        self.column = 0;
+    }
+
+    /// Moves the write position to the start of the next line, adding one if needed.
+    fn new_line(&mut self) {
+        self.line = self.line + 1;
+        self.column = 0;
+        while self.line >= self.lines.len() {
+            self.push_line(LunaLine::new());
+        }
+    }
+
+    /// The first line of the screen that escape sequences address, which
+    /// is as far down as the output goes.
+    fn screen_top(&self) -> usize {
+        self.lines.len().saturating_sub(self.view_lines())
+    }
+
+    /// Moves the write position to a row and column of the screen.
+    fn set_cursor(&mut self, row: usize, column: usize) {
+        // no further down than the lines `push_line` keeps, or this would never end.
+        let target = (self.screen_top() + row.min(self.view_lines() - 1)).min(self.scrollback.max(1) - 1);
+        while target >= self.lines.len() {
+            self.push_line(LunaLine::new());
+        }
+        self.line = target;
+        self.column = column;
     }
 
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                b'\n' => {
-                    self.lines.push(LunaLine::new());
-                },
-                0x20..=0x7e => {
-                    if self.lines.len() == 0 { self.lines.push(LunaLine::new()); }
-                    let idx = self.lines.len() - 1;
-                    self.lines[idx].chars.push(ScreenChar::new(byte as char, self.color));
+        for c in s.chars() {
+            match self.parser.advance(c) {
+                Some(Action::Print(c)) => self.put_char(c),
+                Some(Action::Csi(params, action)) => self.control(&params, action),
+                Some(Action::SaveCursor) => self.saved_cursor = (self.line, self.column),
+                Some(Action::RestoreCursor) => {
+                    let (line, column) = self.saved_cursor;
+                    self.line = line.min(self.lines.len() - 1);
+                    self.column = column;
                 },
+                None => {},
+            }
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\u{0008}' => self.column = self.column.saturating_sub(1),
+            c if !c.is_control() => {
+                if self.lines[self.line].bitmap.width > 0 {
+                    self.new_line();
+                }
+
+                let chr = ScreenChar::with_background(c, self.color, self.background)
+                    .with_attributes(self.attributes);
+                let column = self.column;
+                let line = &mut self.lines[self.line];
+                if column < line.chars.len() {
+                    line.chars[column] = chr;
+                } else {
+                    line.chars.resize(column, ScreenChar::new(' ', self.color));
+                    line.chars.push(chr);
+                }
+                line.cache = Vec::new();
+                self.column = column + 1;
+            },
+            _ => {},
+        }
+    }
+
+    /// Runs a control sequence.
+    fn control(&mut self, params: &Params, action: char) {
+        let row = self.line.saturating_sub(self.screen_top());
+        let n = params.get(0, 1) as usize;
+
+        match action {
+            'm' => self.select_graphic_rendition(params),
+            'H' | 'f' => self.set_cursor(params.get(0, 1) as usize - 1, params.get(1, 1) as usize - 1),
+            'A' => self.set_cursor(row.saturating_sub(n), self.column),
+            'B' => self.set_cursor(row + n, self.column),
+            'C' => self.column = self.column + n,
+            'D' => self.column = self.column.saturating_sub(n),
+            'E' => self.set_cursor(row + n, 0),
+            'F' => self.set_cursor(row.saturating_sub(n), 0),
+            'G' => self.column = n - 1,
+            'J' => {
+                let (start, end) = match params.get(0, 0) {
+                    0 => (self.line + 1, self.lines.len()),
+                    1 => (self.screen_top(), self.line),
+                    _ => (self.screen_top(), self.lines.len()),
+                };
+                for i in start..end {
+                    self.lines[i].clear();
+                }
+                if params.get(0, 0) != 2 {
+                    self.erase_line(params.get(0, 0));
+                }
+            },
+            'K' => self.erase_line(params.get(0, 0)),
+            's' => self.saved_cursor = (self.line, self.column),
+            'u' => {
+                let (line, column) = self.saved_cursor;
+                self.line = line.min(self.lines.len() - 1);
+                self.column = column;
+            },
+            _ => {},
+        }
+    }
+
+    /// Erases the current line after the cursor (`0`), up to the cursor (`1`) or entirely.
+    fn erase_line(&mut self, mode: u16) {
+        let column = self.column;
+        let line = &mut self.lines[self.line];
+        match mode {
+            0 => line.chars.truncate(column),
+            1 => {
+                let end = (column + 1).min(line.chars.len());
+                for chr in &mut line.chars[..end] {
+                    *chr = ScreenChar::new(' ', chr.color());
+                }
+            },
+            _ => line.chars.clear(),
+        }
+        line.cache = Vec::new();
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        if params.len() == 0 {
+            self.reset_rendition();
+        }
+
+        for value in params.iter() {
+            match value {
+                0 => self.reset_rendition(),
+                1 => self.bold = true,
+                4 => self.attributes.underline = true,
+                5 => self.attributes.blink = true,
+                7 => self.attributes.inverse = true,
+                22 => self.bold = false,
+                24 => self.attributes.underline = false,
+                25 => self.attributes.blink = false,
+                27 => self.attributes.inverse = false,
+                30..=37 => self.color = ansi::color(value - 30, self.bold),
+                39 => self.color = Color::LightGray,
+                40..=47 => self.background = ansi::color(value - 40, false),
+                49 => self.background = Color::Black,
+                90..=97 => self.color = ansi::color(value - 90, true),
+                100..=107 => self.background = ansi::color(value - 100, true),
                 _ => {},
             }
         }
     }
+
+    fn reset_rendition(&mut self) {
+        self.color = Color::LightGray;
+        self.background = Color::Black;
+        self.attributes = Attributes::default();
+        self.bold = false;
+    }
 }
 
 impl fmt::Write for LunaRenderer {
@@ -285,9 +957,111 @@ pub fn _color(color: Color) {
     LURE.lock().color = color;
 }
 
+#[doc(hidden)]
+pub fn _background(color: Color) {
+    LURE.lock().background = color;
+}
+
 #[doc(hidden)]
 pub fn _lure_set_enable(enabled: bool) {
     LURE.lock().enabled = enabled;
+    // the console and windows are drawn again once the screen is given back.
+    crate::window::set_enabled(enabled);
+}
+
+#[doc(hidden)]
+pub fn _lure_set_clock(shown: bool) {
+    let mut lure = LURE.lock();
+    lure.clock = shown;
+    lure.lastInputLength = 65535;
+}
+
+/// Keeps the console clock up to date while it's shown.
+pub struct ClockTask {
+    interval: Interval,
+    minute: u8,
+}
+
+impl ClockTask {
+    pub fn new() -> ClockTask {
+        ClockTask {
+            interval: timer::interval(Duration::from_secs(1)),
+            minute: rtc::now().minute,
+        }
+    }
+}
+
+impl Future for ClockTask {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let task = self.get_mut();
+        while let Poll::Ready(_) = Pin::new(&mut task.interval).poll_next(context) {
+            let minute = rtc::now().minute;
+            if minute != task.minute {
+                task.minute = minute;
+                let mut lure = LURE.lock();
+                if lure.clock {
+                    lure.lastInputLength = 65535;
+                    lure.draw();
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// How long blinking characters stay shown, and then hidden.
+const BLINK_PERIOD: Duration = Duration::from_millis(500);
+
+/// Blinks the console's blinking characters.
+pub struct BlinkTask {
+    interval: Interval,
+}
+
+impl BlinkTask {
+    pub fn new() -> BlinkTask {
+        BlinkTask {
+            interval: timer::interval(BLINK_PERIOD),
+        }
+    }
+}
+
+impl Future for BlinkTask {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        // text mode blinks them itself.
+        if cfg!(not(feature = "graphics")) {
+            return Poll::Ready(());
+        }
+
+        let task = self.get_mut();
+        while let Poll::Ready(_) = Pin::new(&mut task.interval).poll_next(context) {
+            vga_buffer::set_blink_visible(!vga_buffer::blink_visible());
+            let mut lure = LURE.lock();
+            if lure.forget_blinking() {
+                lure.lastInputLength = 65535;
+                lure.draw();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[doc(hidden)]
+pub fn _lure_redraw() {
+    let mut lure = LURE.lock();
+    for line in lure.lines.iter_mut() {
+        line.cache = Vec::new();
+    }
+    lure.display.invalidate();
+    lure.lastInputLength = 65535;
+}
+
+#[doc(hidden)]
+pub fn _lure_set_scrollback(lines: usize) {
+    LURE.lock().set_scrollback(lines);
 }
 
 #[doc(hidden)]
@@ -296,7 +1070,7 @@ pub fn _lure_push_bmp(bmp: Bitmap) {
 }
 
 #[doc(hidden)]
-pub fn _lushkey_handler(key: char) {
+pub fn _lushkey_handler(key: LunaKey) {
     LUSH.lock().keyboard_event(key);
 }
 
@@ -306,15 +1080,51 @@ pub fn _lush_pop_listener() {
 }
 
 #[doc(hidden)]
-pub fn _lush_push_listener(key_listener: fn(char)) {
+pub fn _lush_push_listener(key_listener: fn(LunaKey)) {
     LULI.lock().key_listeners.push(key_listener);
 }
 
+#[doc(hidden)]
+pub fn _lushmouse_handler(event: MouseEvent) {
+    // a listener may push or pop listeners, so don't hold the lock while it runs.
+    let listener = LULI.lock().mouse_listeners.last().copied();
+    match listener {
+        Some(listener) => listener(event),
+        None => crate::window::mouse_event(event),
+    }
+}
+
+#[doc(hidden)]
+pub fn _lush_pop_mouse_listener() {
+    LULI.lock().mouse_listeners.pop();
+}
+
+#[doc(hidden)]
+pub fn _lush_push_mouse_listener(mouse_listener: fn(MouseEvent)) {
+    LULI.lock().mouse_listeners.push(mouse_listener);
+}
+
 #[macro_export]
 macro_rules! lure_enabled {
     ($enabled:expr) => ($crate::shell::_lure_set_enable($enabled));
 }
 
+#[macro_export]
+macro_rules! lure_clock {
+    ($shown:expr) => ($crate::shell::_lure_set_clock($shown));
+}
+
+/// Draws the whole console again on its next update, as after switching fonts.
+#[macro_export]
+macro_rules! lure_redraw {
+    () => ($crate::shell::_lure_redraw());
+}
+
+#[macro_export]
+macro_rules! lure_scrollback {
+    ($lines:expr) => ($crate::shell::_lure_set_scrollback($lines));
+}
+
 #[macro_export]
 macro_rules! lure_bmp {
     ($bitmap:expr) => ($crate::shell::_lure_push_bmp($bitmap));
@@ -330,20 +1140,63 @@ macro_rules! lush_keypush {
     ($listener:expr) => ($crate::shell::_lush_push_listener($listener));
 }
 
+#[macro_export]
+macro_rules! lush_mousepop {
+    () => ($crate::shell::_lush_pop_mouse_listener());
+}
+
+#[macro_export]
+macro_rules! lush_mousepush {
+    ($listener:expr) => ($crate::shell::_lush_push_mouse_listener($listener));
+}
+
 #[macro_export]
 macro_rules! LUSHKeyHandler {
     ($key:expr) => ($crate::shell::_lushkey_handler($key));
 }
 
+#[macro_export]
+macro_rules! LUSHMouseHandler {
+    ($event:expr) => ($crate::shell::_lushmouse_handler($event));
+}
+
 #[doc(hidden)]
-pub fn _lushadd_command(key: Vec<char>, handler: fn(Vec<char>)) {
-    LUSH.lock().commands.push(key);
-    LUSH.lock().handlers.push(handler);
+pub fn _lushadd_command(command: Command) {
+    LUSH.lock().add_command(command);
 }
 
+#[doc(hidden)]
+pub fn _lushset_completer(name: &str, complete: fn(&[String], &str) -> Vec<String>) {
+    if let Some(command) = LUSH.lock().commands.get_mut(name) {
+        command.complete = Some(complete);
+    }
+}
+
+/// Sets the argument completion callback of a registered command.
+#[macro_export]
+macro_rules! LUSHComplete {
+    ($name:expr, $complete:expr) => ($crate::shell::_lushset_completer($name, $complete));
+}
+
+/// Registers a shell command: `LUSHAddCommand!(name, [aliases], handler, usage, summary)`.
+///
+/// The aliases, and the usage and summary shown by `help`, may be left out.
 #[macro_export]
 macro_rules! LUSHAddCommand {
-    ($key:expr, $handler:expr) => ($crate::shell::_lushadd_command($key, $handler));
+    ($name:expr, [$($alias:expr),*], $handler:expr, $usage:expr, $summary:expr) => (
+        $crate::shell::_lushadd_command($crate::shell::Command {
+            name: $name,
+            aliases: &[$($alias),*],
+            usage: $usage,
+            summary: $summary,
+            handler: $crate::shell::Handler::Extern($handler),
+            complete: None,
+        })
+    );
+    ($name:expr, $handler:expr, $usage:expr, $summary:expr) => (
+        $crate::LUSHAddCommand!($name, [], $handler, $usage, $summary)
+    );
+    ($name:expr, $handler:expr) => ($crate::LUSHAddCommand!($name, [], $handler, $name, ""));
 }
 
 #[macro_export]
@@ -356,6 +1209,11 @@ macro_rules! color {
     ($color:expr) => ($crate::shell::_color($color));
 }
 
+#[macro_export]
+macro_rules! background {
+    ($color:expr) => ($crate::shell::_background($color));
+}
+
 /// Like the `print!` macro in the standard library, but prints to the VGA text buffer.
 #[macro_export]
 macro_rules! print {
@@ -367,4 +1225,79 @@ macro_rules! print {
 macro_rules! println {
     () => ($crate::print!("\n"));
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
-}
\ No newline at end of file
+}
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+#[test_case]
+fn test_tokenize_whitespace() {
+    serial_print!("test_tokenize_whitespace...");
+    assert_eq!(tokenize("  echo   a\tb  "), Ok(vec![String::from("echo"), String::from("a"), String::from("b")]));
+    assert_eq!(tokenize("   "), Ok(vec![]));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_tokenize_quotes_and_escapes() {
+    serial_print!("test_tokenize_quotes_and_escapes...");
+    assert_eq!(tokenize(r#"echo "a  b" 'c \d' e\ f """#), Ok(vec![
+        String::from("echo"),
+        String::from("a  b"),
+        String::from(r"c \d"),
+        String::from("e f"),
+        String::from(""),
+    ]));
+    assert_eq!(tokenize(r#"say "\"hi\"\\""#), Ok(vec![String::from("say"), String::from(r#""hi"\"#)]));
+    assert_eq!(tokenize("echo 'oops"), Err(TokenizeError::UnterminatedQuote('\'')));
+    assert_eq!(tokenize("echo \\"), Err(TokenizeError::TrailingEscape));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_set_scrollback() {
+    serial_print!("test_set_scrollback...");
+    let mut lure = LURE.lock();
+    let scrollback = lure.scrollback;
+    for _ in 0..lure.view_lines() + 10 {
+        lure.push_line(LunaLine::new());
+    }
+
+    // never fewer lines than fill the screen, and the rest are dropped.
+    lure.set_scrollback(0);
+    assert_eq!(lure.scrollback, lure.view_lines());
+    assert_eq!(lure.lines.len(), lure.view_lines());
+    assert!(lure.line < lure.lines.len());
+
+    lure.set_scrollback(scrollback);
+    assert_eq!(lure.scrollback, scrollback);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_set_cursor_with_short_scrollback() {
+    serial_print!("test_set_cursor_with_short_scrollback...");
+    let mut lure = LURE.lock();
+    let scrollback = lure.scrollback;
+
+    // shorter than the screen, as the field can still be set directly.
+    lure.scrollback = 2;
+    lure.trim_scrollback();
+    lure.write_string("\x1b[20;1Hx");
+    assert_eq!(lure.lines.len(), 2);
+    assert_eq!(lure.line, 1);
+
+    lure.set_scrollback(scrollback);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_line_rows() {
+    serial_print!("test_line_rows...");
+    let mut line = LunaLine::new();
+    assert_eq!(line.rows(80), 1);
+    line.chars = vec![ScreenChar::new('a', Color::White); 81];
+    assert_eq!(line.rows(80), 2);
+    // a screen too narrow for any columns still counts one per row.
+    assert_eq!(line.rows(0), 81);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,151 @@
+use super::{queue::ArrayQueue, Task, TaskId};
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::mem::ManuallyDrop;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use x86_64::instructions::interrupts;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// How many wake-ups can wait to be run at once.
+const QUEUE_SIZE: usize = 100;
+
+/// Runs tasks when they're woken, halting while there's nothing to do.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    /// Tasks that have been woken, pushed to by their wakers.
+    ready: Arc<ArrayQueue<TaskId>>,
+    wakers: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor {
+            tasks: BTreeMap::new(),
+            ready: Arc::new(ArrayQueue::new(QUEUE_SIZE)),
+            wakers: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a task, which is polled the next time ready tasks are run.
+    pub fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_some() {
+            panic!("task {:?} was spawned twice", id);
+        }
+        self.ready.push(id).expect("ready queue is full");
+    }
+
+    /// Polls every task that has been woken, until none are left.
+    pub fn run_ready_tasks(&mut self) {
+        let Executor { tasks, ready, wakers } = self;
+
+        while let Some(id) = ready.pop() {
+            // tasks can be woken again after they've finished.
+            let task = match tasks.get_mut(&id) {
+                Some(task) => task,
+                None => continue,
+            };
+
+            let waker = wakers.entry(id).or_insert_with(|| task_waker(id, ready.clone()));
+            let mut context = Context::from_waker(waker);
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                tasks.remove(&id);
+                wakers.remove(&id);
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Halts until the next interrupt if no task has been woken.
+    fn sleep_if_idle(&self) {
+        // interrupts are off while checking, so a wake-up can't slip in before `hlt`.
+        interrupts::disable();
+        if self.ready.is_empty() {
+            enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Enables interrupts and halts until the next one. `sti` takes effect after
+/// the instruction that follows it, so none can be handled in between.
+fn enable_and_hlt() {
+    unsafe { asm!("sti; hlt" :::: "volatile") };
+}
+
+/// Wakes a task by pushing its ID onto the executor's ready queue.
+struct TaskWaker {
+    id: TaskId,
+    ready: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn wake(&self) {
+        // a full queue already has the task in it, or will be worked through first.
+        let _ = self.ready.push(self.id);
+    }
+}
+
+fn task_waker(id: TaskId, ready: Arc<ArrayQueue<TaskId>>) -> Waker {
+    let waker = Arc::new(TaskWaker { id: id, ready: ready });
+    unsafe { Waker::from_raw(RawWaker::new(Arc::into_raw(waker) as *const (), &VTABLE)) }
+}
+
+/// A `RawWaker`'s data is an `Arc<TaskWaker>` turned into a pointer.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    let waker = ManuallyDrop::new(Arc::from_raw(data as *const TaskWaker));
+    let clone = Arc::clone(&waker);
+    RawWaker::new(Arc::into_raw(clone) as *const (), &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    Arc::from_raw(data as *const TaskWaker).wake();
+}
+
+unsafe fn wake_by_ref(data: *const ()) {
+    ManuallyDrop::new(Arc::from_raw(data as *const TaskWaker)).wake();
+}
+
+unsafe fn drop_waker(data: *const ()) {
+    drop(Arc::from_raw(data as *const TaskWaker));
+}
+
+#[test_case]
+fn test_executor_runs_woken_tasks() {
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    serial_print!("test_executor_runs_woken_tasks...");
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Pending until it's been polled three times, waking itself each time.
+    struct Countdown;
+    impl Future for Countdown {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            if POLLS.fetch_add(1, Ordering::SeqCst) == 2 {
+                return Poll::Ready(());
+            }
+            context.waker().clone().wake();
+            Poll::Pending
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(Countdown));
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 3);
+    assert!(executor.tasks.is_empty() && executor.wakers.is_empty());
+    serial_println!("[ok]");
+}
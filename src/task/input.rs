@@ -0,0 +1,140 @@
+use super::{queue::ArrayQueue, Stream, WakerSlot};
+use crate::{ansi, mouse, serial, serial_println, shell::LunaKey, vga_apply, vga_buffer::WRITER, LUSHKeyHandler, LUSHMouseHandler};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use pc_keyboard::{layouts::Us104Key, DecodedKey, KeyCode, KeyState, Keyboard, ScancodeSet1};
+use spin::Once;
+
+/// How many bytes of input can wait before they're dropped.
+const QUEUE_SIZE: usize = 256;
+
+/// A byte received by one of the input interrupt handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Scancode(u8),
+    Serial(u8),
+    Mouse(u8),
+}
+
+static QUEUE: Once<ArrayQueue<Input>> = Once::new();
+static WAKER: WakerSlot = WakerSlot::new();
+/// Input dropped because the queue was full, reported by the `InputTask`.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Queues input for the `InputTask`. Called by the interrupt handlers, so it
+/// doesn't allocate, and only locks what's never locked with interrupts on.
+pub fn push(input: Input) {
+    // input arriving before the stream is created has nowhere to go.
+    if let Some(queue) = QUEUE.r#try() {
+        if queue.push(input).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+        WAKER.wake();
+    }
+}
+
+/// The input queued by the interrupt handlers.
+pub struct InputStream {
+    _private: (),
+}
+
+impl InputStream {
+    /// Creates the queue. There's only one, so only one stream should be made.
+    pub fn new() -> InputStream {
+        QUEUE.call_once(|| ArrayQueue::new(QUEUE_SIZE));
+        InputStream { _private: () }
+    }
+}
+
+impl Stream for InputStream {
+    type Item = Input;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Input>> {
+        let queue = QUEUE.r#try().expect("input queue not created");
+        if let Some(input) = queue.pop() {
+            return Poll::Ready(Some(input));
+        }
+
+        // look again once registered, in case input came in before then.
+        WAKER.register(context.waker());
+        match queue.pop() {
+            Some(input) => Poll::Ready(Some(input)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes keyboard, serial and mouse input and passes it to the shell,
+/// outside of the interrupt handlers.
+pub struct InputTask {
+    stream: InputStream,
+    keyboard: Keyboard<Us104Key, ScancodeSet1>,
+    /// The keyboard doesn't expose its modifiers, so shift is tracked here.
+    shift: bool,
+    parser: ansi::Parser,
+}
+
+impl InputTask {
+    pub fn new() -> InputTask {
+        InputTask {
+            stream: InputStream::new(),
+            keyboard: Keyboard::new(Us104Key, ScancodeSet1),
+            shift: false,
+            parser: ansi::Parser::new(),
+        }
+    }
+
+    fn scancode(&mut self, scancode: u8) {
+        if let Ok(Some(key_event)) = self.keyboard.add_byte(scancode) {
+            if key_event.code == KeyCode::ShiftLeft || key_event.code == KeyCode::ShiftRight {
+                self.shift = key_event.state == KeyState::Down;
+            }
+
+            if let Some(key) = self.keyboard.process_keyevent(key_event) {
+                match key {
+                    DecodedKey::Unicode(key) => LUSHKeyHandler!(LunaKey::Char(key)),
+                    DecodedKey::RawKey(key) if self.shift => LUSHKeyHandler!(LunaKey::Shifted(key)),
+                    DecodedKey::RawKey(key) => LUSHKeyHandler!(LunaKey::Raw(key)),
+                }
+            }
+        }
+    }
+
+    fn serial(&mut self, byte: u8) {
+        if let Some(key) = self.parser.advance(byte as char).and_then(serial::decode_key) {
+            LUSHKeyHandler!(key);
+        }
+    }
+
+    fn mouse(&mut self, byte: u8) {
+        if let Some(event) = mouse::add_byte(byte) {
+            WRITER.lock().set_cursor(Some((event.x, event.y)));
+            LUSHMouseHandler!(event);
+            vga_apply!();
+        }
+    }
+}
+
+impl Future for InputTask {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let task = self.get_mut();
+        loop {
+            let dropped = DROPPED.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                serial_println!("WARNING: input queue full; dropped {} bytes", dropped);
+            }
+
+            match Pin::new(&mut task.stream).poll_next(context) {
+                Poll::Ready(Some(Input::Scancode(scancode))) => task.scancode(scancode),
+                Poll::Ready(Some(Input::Serial(byte))) => task.serial(byte),
+                Poll::Ready(Some(Input::Mouse(byte))) => task.mouse(byte),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
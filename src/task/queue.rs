@@ -0,0 +1,122 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// A fixed-size queue that can be pushed to from interrupt handlers, since
+/// it never locks or allocates after it's created.
+pub struct ArrayQueue<T> {
+    slots: Box<[Slot<T>]>,
+    /// The position of the next value to pop.
+    head: AtomicUsize,
+    /// The position the next value is pushed at.
+    tail: AtomicUsize,
+}
+
+struct Slot<T> {
+    /// The position this slot is next pushed at, or one past it once it holds a value.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    pub fn new(capacity: usize) -> ArrayQueue<T> {
+        let slots: Vec<Slot<T>> = (0..capacity.max(1))
+            .map(|i| Slot {
+                sequence: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+
+        ArrayQueue {
+            slots: slots.into_boxed_slice(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds a value at the back, or hands it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let capacity = self.slots.len();
+        loop {
+            let tail = self.tail.load(Ordering::Relaxed);
+            let slot = &self.slots[tail % capacity];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            if sequence == tail {
+                // claim the slot, unless an interrupt pushed to it first.
+                if self.tail.compare_exchange_weak(tail, tail + 1, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                    unsafe { (*slot.value.get()).as_mut_ptr().write(value) };
+                    slot.sequence.store(tail + 1, Ordering::Release);
+                    return Ok(());
+                }
+            } else if sequence < tail {
+                // the slot still holds the value pushed a lap ago.
+                return Err(value);
+            }
+        }
+    }
+
+    /// Takes the value at the front, if there is one.
+    pub fn pop(&self) -> Option<T> {
+        let capacity = self.slots.len();
+        loop {
+            let head = self.head.load(Ordering::Relaxed);
+            let slot = &self.slots[head % capacity];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            if sequence == head + 1 {
+                if self.head.compare_exchange_weak(head, head + 1, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                    let value = unsafe { (*slot.value.get()).as_ptr().read() };
+                    slot.sequence.store(head + capacity, Ordering::Release);
+                    return Some(value);
+                }
+            } else if sequence < head + 1 {
+                // nothing has been pushed here yet.
+                return None;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        self.slots[head % self.slots.len()].sequence.load(Ordering::Acquire) != head + 1
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[test_case]
+fn test_array_queue() {
+    serial_print!("test_array_queue...");
+    let queue = ArrayQueue::new(3);
+    assert!(queue.is_empty());
+
+    // go round a few times so the positions wrap past the end of the slots.
+    for lap in 0..3 {
+        assert_eq!(queue.push(lap), Ok(()));
+        assert_eq!(queue.push(lap + 1), Ok(()));
+        assert_eq!(queue.push(lap + 2), Ok(()));
+        assert_eq!(queue.push(99), Err(99));
+        assert_eq!(queue.pop(), Some(lap));
+        assert_eq!(queue.pop(), Some(lap + 1));
+        assert_eq!(queue.pop(), Some(lap + 2));
+        assert_eq!(queue.pop(), None);
+    }
+    assert!(queue.is_empty());
+    serial_println!("[ok]");
+}
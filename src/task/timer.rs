@@ -0,0 +1,181 @@
+use super::Stream;
+use crate::time::Instant;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// The tasks waiting on the timer: a key for each `Deadline`, the tick it
+/// waits for, and its waker.
+static WAITING: Mutex<Vec<(u64, u64, Waker)>> = Mutex::new(Vec::new());
+
+/// The earliest tick in `WAITING`, so most ticks don't need to look at it.
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::max_value());
+
+/// Wakes the tasks whose deadline has come. Called by the timer interrupt handler.
+pub fn tick(now: u64) {
+    if now < NEXT_DEADLINE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // `WAITING` is only locked elsewhere with interrupts off, so this can't spin.
+    let mut waiting = WAITING.lock();
+    let mut next = u64::max_value();
+    for (_, deadline, waker) in waiting.iter_mut() {
+        if *deadline <= now {
+            waker.wake_by_ref();
+            // wake it once; it's registered again if it polls too early.
+            *deadline = u64::max_value();
+        } else {
+            next = next.min(*deadline);
+        }
+    }
+    NEXT_DEADLINE.store(next, Ordering::Relaxed);
+}
+
+/// A tick to wait for, registered with the timer while something waits on it.
+struct Deadline {
+    key: u64,
+    at: Instant,
+}
+
+impl Deadline {
+    fn new(at: Instant) -> Deadline {
+        static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+        Deadline {
+            key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+            at: at,
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.at {
+            self.unregister();
+            return Poll::Ready(());
+        }
+
+        let (key, at) = (self.key, self.at.tick());
+        interrupts::without_interrupts(|| {
+            let mut waiting = WAITING.lock();
+            match waiting.iter_mut().find(|entry| entry.0 == key) {
+                Some(entry) => {
+                    entry.1 = at;
+                    if !entry.2.will_wake(context.waker()) {
+                        entry.2 = context.waker().clone();
+                    }
+                },
+                None => waiting.push((key, at, context.waker().clone())),
+            }
+            if at < NEXT_DEADLINE.load(Ordering::Relaxed) {
+                NEXT_DEADLINE.store(at, Ordering::Relaxed);
+            }
+        });
+        Poll::Pending
+    }
+
+    fn unregister(&self) {
+        let key = self.key;
+        // the waker is dropped here rather than in the interrupt handler.
+        let removed = interrupts::without_interrupts(|| {
+            let mut waiting = WAITING.lock();
+            let index = waiting.iter().position(|entry| entry.0 == key)?;
+            Some(waiting.swap_remove(index))
+        });
+        drop(removed);
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// A future that completes once a duration has passed.
+pub struct Sleep {
+    deadline: Deadline,
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Deadline::new(Instant::now().add(duration)),
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        self.get_mut().deadline.poll(context)
+    }
+}
+
+/// A stream that yields once every period.
+pub struct Interval {
+    deadline: Deadline,
+    period: Duration,
+}
+
+pub fn interval(period: Duration) -> Interval {
+    Interval {
+        deadline: Deadline::new(Instant::now().add(period)),
+        period: period,
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<()>> {
+        let interval = self.get_mut();
+        match interval.deadline.poll(context) {
+            Poll::Ready(()) => {
+                // skip the periods that were missed rather than yielding for each.
+                let now = Instant::now();
+                let mut next = interval.deadline.at.add(interval.period);
+                while next <= now && interval.period > Duration::from_millis(0) {
+                    next = next.add(interval.period);
+                }
+                interval.deadline.at = next.max(now.add(Duration::from_millis(1)));
+                Poll::Ready(Some(()))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_sleep_future() {
+    use core::sync::atomic::AtomicUsize;
+    use core::task::{RawWaker, RawWakerVTable};
+
+    serial_print!("test_sleep_future...");
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop_waker);
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    fn wake(_: *const ()) {
+        WAKES.fetch_add(1, Ordering::SeqCst);
+    }
+    fn drop_waker(_: *const ()) {}
+
+    let waker = unsafe { Waker::from_raw(clone(core::ptr::null())) };
+    let mut context = Context::from_waker(&waker);
+    let mut sleep = sleep(Duration::from_millis(5));
+    assert_eq!(Pin::new(&mut sleep).poll(&mut context), Poll::Pending);
+
+    // the timer interrupt wakes it once, and it's gone from the timer when it's done.
+    crate::time::sleep(Duration::from_millis(10));
+    assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+    assert_eq!(Pin::new(&mut sleep).poll(&mut context), Poll::Ready(()));
+    assert!(WAITING.lock().is_empty());
+    serial_println!("[ok]");
+}
@@ -0,0 +1,146 @@
+use crate::{
+    display::Display,
+    vga_buffer::{Bitmap, Color, ScreenChar},
+};
+use volatile::Volatile;
+use x86_64::instructions::port::Port;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// The height of the text buffer.
+const TEXT_HEIGHT: usize = 25;
+/// The width of the text buffer.
+const TEXT_WIDTH: usize = 80;
+
+/// The upper half of code page 437, the character set of VGA text mode.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00a0}',
+];
+
+/// Shown for characters code page 437 doesn't have.
+const REPLACEMENT: u8 = 0xfe;
+
+/// The code page 437 byte that displays a character.
+pub fn cp437(c: char) -> u8 {
+    match c {
+        ' '..='~' => c as u8,
+        _ => match CP437.iter().position(|&other| other == c) {
+            Some(i) => 0x80 + i as u8,
+            None => REPLACEMENT,
+        },
+    }
+}
+
+/// The attribute byte of a character: the background color in the high
+/// nibble, the foreground in the low one, and bit 7 for blinking.
+fn attribute(chr: &ScreenChar) -> u8 {
+    let attributes = chr.attributes();
+    let (fg, bg) = if attributes.inverse {
+        (chr.background(), chr.color())
+    } else {
+        (chr.color(), chr.background())
+    };
+
+    // bit 7 blinks rather than brightening the background.
+    let blink = if attributes.blink { 0x80 } else { 0 };
+    blink | (bg as u8 & 0x07) << 4 | fg as u8
+}
+
+#[repr(transparent)]
+struct Buffer {
+    chars: [[Volatile<u16>; TEXT_WIDTH]; TEXT_HEIGHT],
+}
+
+/// Draws the console in the 80x25 VGA text buffer at 0xb8000.
+pub struct TextMode {
+    buffer: &'static mut Buffer,
+}
+
+impl TextMode {
+    /// Takes over the text buffer, hiding the hardware cursor since the
+    /// console draws its own.
+    pub fn new() -> TextMode {
+        let mut index: Port<u8> = Port::new(0x3D4);
+        let mut data: Port<u8> = Port::new(0x3D5);
+        unsafe {
+            // bit 5 of the cursor start register turns the cursor off.
+            index.write(0x0A);
+            data.write(0x20);
+        }
+
+        TextMode {
+            buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        }
+    }
+
+    fn write(&mut self, column: usize, row: usize, chr: ScreenChar) {
+        if column < TEXT_WIDTH && row < TEXT_HEIGHT {
+            let value = (attribute(&chr) as u16) << 8 | cp437(chr.character()) as u16;
+            self.buffer.chars[row][column].write(value);
+        }
+    }
+}
+
+impl Display for TextMode {
+    fn columns(&self) -> usize {
+        TEXT_WIDTH
+    }
+
+    fn view_lines(&self) -> usize {
+        TEXT_HEIGHT - 2
+    }
+
+    fn clear(&mut self) {
+        for row in 0..TEXT_HEIGHT {
+            for column in 0..TEXT_WIDTH {
+                self.write(column, row, ScreenChar::new(' ', Color::LightGray));
+            }
+        }
+    }
+
+    fn draw_char(&mut self, column: usize, row: usize, chr: ScreenChar) {
+        self.write(column, row, chr);
+    }
+
+    fn draw_input_char(&mut self, column: usize, chr: ScreenChar) {
+        self.write(column, TEXT_HEIGHT - 1, chr);
+    }
+
+    /// Shows the bitmap as blocks of color, sampling the middle of each 8x8 cell.
+    fn draw_bitmap(&mut self, row: usize, bmp: &Bitmap) {
+        for column in 0..(bmp.width / 8).min(TEXT_WIDTH) {
+            let pixel = bmp.data.get(4.min(bmp.height.saturating_sub(1)) * bmp.width + column * 8 + 4);
+            let color = Color::ALL[*pixel.unwrap_or(&0) as usize % 16];
+            self.write(column, row, ScreenChar::new('█', color));
+        }
+    }
+
+    /// Writing characters is already as quick as copying them.
+    fn capture_row(&mut self, _row: usize) -> Option<Bitmap> {
+        None
+    }
+
+    /// Nothing is kept about what's on screen.
+    fn invalidate(&mut self) {}
+
+    /// Characters are written straight to the screen.
+    fn present(&mut self) {}
+}
+
+#[test_case]
+fn test_cp437() {
+    serial_print!("test_cp437...");
+    assert_eq!(cp437('A'), b'A');
+    assert_eq!(cp437('é'), 0x82);
+    assert_eq!(cp437('─'), 0xc4);
+    assert_eq!(cp437('\u{2603}'), REPLACEMENT);
+    serial_println!("[ok]");
+}
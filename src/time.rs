@@ -0,0 +1,146 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::{hlt, interrupts, port::Port};
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+/// How often the timer interrupt fires once `init` has programmed the PIT.
+pub const TICK_HZ: u64 = 1000;
+
+/// The frequency the PIT counts down at.
+const PIT_HZ: u64 = 1_193_182;
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+/// Timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Programs the PIT to interrupt `TICK_HZ` times a second.
+pub fn init() {
+    let divisor = (PIT_HZ / TICK_HZ) as u16;
+    let mut command: Port<u8> = Port::new(PIT_COMMAND);
+    let mut channel0: Port<u8> = Port::new(PIT_CHANNEL0);
+    unsafe {
+        // channel 0, low byte then high byte, square wave.
+        command.write(0x36);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Counts a timer interrupt, returning the new count. Called by the timer
+/// interrupt handler.
+pub fn tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+fn to_duration(ticks: u64) -> Duration {
+    Duration::from_millis(ticks * 1000 / TICK_HZ)
+}
+
+fn to_ticks(duration: Duration) -> u64 {
+    // round up so a short sleep still waits. this can't overflow a u128, and
+    // anything longer than a u64 of ticks is as good as forever.
+    let ticks = (duration.as_micros() * TICK_HZ as u128 + 999_999) / 1_000_000;
+    ticks.min(u64::max_value() as u128) as u64
+}
+
+/// How long since the kernel started counting time.
+pub fn uptime() -> Duration {
+    to_duration(ticks())
+}
+
+/// A point in time, for measuring how long something takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant { ticks: ticks() }
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        to_duration(self.ticks.saturating_sub(earlier.ticks))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// The timer tick this instant falls on, as counted by `ticks`.
+    pub fn tick(&self) -> u64 {
+        self.ticks
+    }
+
+    /// The instant a duration after this one, or the last instant there is.
+    pub fn add(&self, duration: Duration) -> Instant {
+        Instant { ticks: self.ticks.saturating_add(to_ticks(duration)) }
+    }
+}
+
+/// Halts until the given instant has passed.
+///
+/// Interrupts are enabled while waiting for the timer, and disabled again
+/// afterwards if they were off.
+pub fn sleep_until(deadline: Instant) {
+    let enabled = interrupts::are_enabled();
+    while TICKS.load(Ordering::Relaxed) < deadline.ticks {
+        interrupts::enable();
+        hlt();
+    }
+    if !enabled {
+        interrupts::disable();
+    }
+}
+
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now().add(duration));
+}
+
+/// Paces a drawing loop to a steady number of frames per second, up to one
+/// frame a tick.
+pub struct FrameLimiter {
+    frame: Duration,
+    next: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(fps: usize) -> FrameLimiter {
+        let fps = fps.max(1).min(TICK_HZ as usize) as u64;
+        let frame = Duration::from_micros(1_000_000 / fps);
+        FrameLimiter {
+            frame: frame,
+            next: Instant::now().add(frame),
+        }
+    }
+
+    /// Halts until it's time for the next frame.
+    pub fn wait(&mut self) {
+        sleep_until(self.next);
+        // a frame that ran long pushes the next one back instead of rushing to catch up.
+        self.next = self.next.max(Instant::now()).add(self.frame);
+    }
+}
+
+#[test_case]
+fn test_sleep() {
+    serial_print!("test_sleep...");
+    let start = Instant::now();
+    sleep(Duration::from_millis(20));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert!(uptime() >= start.elapsed());
+
+    // far-off deadlines saturate instead of overflowing.
+    let never = start.add(Duration::from_secs(u64::max_value()));
+    assert!(never > start.add(Duration::from_secs(1_000_000)));
+    assert_eq!(never.add(Duration::from_millis(1)), never);
+    serial_println!("[ok]");
+}
@@ -2,8 +2,11 @@ use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
 use alloc::{vec::Vec};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use font8x8::{BASIC_FONTS, UnicodeFonts};
+use crate::font::{self, Font};
+use crate::palette;
 
 #[cfg(test)]
 use crate::{serial_print, serial_println};
@@ -12,10 +15,7 @@ lazy_static! {
     /// A global `Writer` instance that can be used for printing to the VGA text buffer.
     ///
     /// Used by the `print!` and `println!` macros.
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        vgabuffer: unsafe { &mut *(0xa0000 as *mut Buffer) },
-        buffer: [[Color::Black as u8; BUFFER_WIDTH]; BUFFER_HEIGHT],
-    });
+    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer::new(Framebuffer::mode13h()));
 }
 
 /// The standard color palette in VGA text mode.
@@ -41,130 +41,1061 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    /// Every named color, in palette order.
+    pub const ALL: [Color; 16] = [
+        Color::Black, Color::Blue, Color::Green, Color::Cyan,
+        Color::Red, Color::Magenta, Color::Brown, Color::LightGray,
+        Color::DarkGray, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+        Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+    ];
+
+    /// The lowercase name of the color, as typed in the shell.
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::Black => "black",
+            Color::Blue => "blue",
+            Color::Green => "green",
+            Color::Cyan => "cyan",
+            Color::Red => "red",
+            Color::Magenta => "magenta",
+            Color::Brown => "brown",
+            Color::LightGray => "lightgray",
+            Color::DarkGray => "darkgray",
+            Color::LightBlue => "lightblue",
+            Color::LightGreen => "lightgreen",
+            Color::LightCyan => "lightcyan",
+            Color::LightRed => "lightred",
+            Color::Pink => "pink",
+            Color::Yellow => "yellow",
+            Color::White => "white",
+        }
+    }
+
+    /// The red, green and blue levels the color has in the default palette.
+    pub fn rgb(self) -> [u8; 3] {
+        match self {
+            Color::Black => [0, 0, 0],
+            Color::Blue => [0, 0, 170],
+            Color::Green => [0, 170, 0],
+            Color::Cyan => [0, 170, 170],
+            Color::Red => [170, 0, 0],
+            Color::Magenta => [170, 0, 170],
+            Color::Brown => [170, 85, 0],
+            Color::LightGray => [170, 170, 170],
+            Color::DarkGray => [85, 85, 85],
+            Color::LightBlue => [85, 85, 255],
+            Color::LightGreen => [85, 255, 85],
+            Color::LightCyan => [85, 255, 255],
+            Color::LightRed => [255, 85, 85],
+            Color::Pink => [255, 85, 255],
+            Color::Yellow => [255, 255, 85],
+            Color::White => [255, 255, 255],
+        }
+    }
+
+    /// The named color closest to an RGB value.
+    pub fn nearest(rgb: [u8; 3]) -> Color {
+        let distance = |color: &Color| {
+            color.rgb().iter().zip(rgb.iter())
+                .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+                .sum::<i32>()
+        };
+        *Color::ALL.iter().min_by_key(|color| distance(color)).unwrap()
+    }
+
+    /// Looks up a color by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Color> {
+        Color::ALL.iter().copied().find(|color| color.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Bitmap {
     pub data: Vec<u8>,
     pub height: usize,
     pub width: usize,
+    /// A color that isn't drawn, letting what's underneath show through.
+    pub color_key: Option<u8>,
 }
 
 impl Bitmap {
-    /// Create a new `ColorCode` with the given foreground and background colors.
+    /// Create a new opaque `Bitmap` from rows of palette indices.
     pub fn new(width: usize, height: usize, data: Vec<u8>) -> Bitmap {
         Bitmap {
             data: data,
             height: height,
             width: width,
+            color_key: None,
+        }
+    }
+
+    /// Makes pixels of the given color transparent.
+    pub fn with_color_key(mut self, key: u8) -> Bitmap {
+        self.color_key = Some(key);
+        self
+    }
+}
+
+/// How `Writer::blend_bmp` combines a bitmap with what's already drawn.
+#[derive(Clone, Copy)]
+pub enum BlendMode<'a> {
+    /// Copy every pixel, ignoring the color key.
+    Opaque,
+    /// Copy every pixel except those matching the bitmap's color key.
+    ColorKey,
+    /// Draw each pixel's entry in the table instead, for tinting. Keyed pixels are skipped.
+    Lookup(&'a [u8; 256]),
+    /// Replace the pixels underneath with their entry in the table, for shadows and highlights.
+    /// The bitmap only acts as a mask, so keyed pixels are left alone.
+    Shade(&'a [u8; 256]),
+}
+
+/// The red, green and blue levels of each of the 256 palette indices.
+#[derive(Clone)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 256],
+}
+
+impl Palette {
+    /// Create a palette with every entry black.
+    pub fn new() -> Palette {
+        Palette {
+            colors: [[0; 3]; 256],
+        }
+    }
+
+    /// A lookup table taking each entry to the closest named color.
+    pub fn remap_table(&self) -> [u8; 256] {
+        let mut table = [0; 256];
+        for (i, rgb) in self.colors.iter().enumerate() {
+            table[i] = Color::nearest(*rgb) as u8;
         }
+        table
     }
 }
 
-/// A screen character in the VGA text buffer, consisting of an ASCII character and a `ColorCode`.
+/// How a `ScreenChar` is displayed, beyond its colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Attributes {
+    /// Swap the foreground and background colors.
+    pub inverse: bool,
+    /// Draw a line along the bottom row of the glyph.
+    pub underline: bool,
+    /// Hide the glyph while the blink phase is off.
+    pub blink: bool,
+}
+
+/// A screen character, consisting of a character, its colors and its `Attributes`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct ScreenChar {
     character: char,
     color: Color,
+    background: Color,
+    attributes: Attributes,
 }
 
 impl ScreenChar {
-    /// Create a new `ColorCode` with the given foreground and background colors.
+    /// Create a new `ScreenChar` with the given foreground color on black.
     pub fn new(character: char, color: Color) -> ScreenChar {
+        ScreenChar::with_background(character, color, Color::Black)
+    }
+
+    /// Create a new `ScreenChar` with the given foreground and background colors.
+    pub fn with_background(character: char, color: Color, background: Color) -> ScreenChar {
         ScreenChar {
             character: character,
-            color: color
+            color: color,
+            background: background,
+            attributes: Attributes::default(),
         }
     }
+
+    /// Returns the same character with the given attributes.
+    pub fn with_attributes(self, attributes: Attributes) -> ScreenChar {
+        ScreenChar {
+            attributes: attributes,
+            ..self
+        }
+    }
+
+    pub fn character(&self) -> char {
+        self.character
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn background(&self) -> Color {
+        self.background
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
 }
 
-/// The height of the vga buffer.
-const BUFFER_HEIGHT: usize = 200;
-/// The width of the vga buffer.
-const BUFFER_WIDTH: usize = 320;
+/// Whether blinking characters are currently shown.
+static BLINK_VISIBLE: AtomicBool = AtomicBool::new(true);
 
-/// The height of the vga buffer.
-const CHAR_WIDTH: usize = 8;
-/// The width of the vga buffer.
-const CHAR_HEIGHT: usize = 8;
+/// Sets whether blinking characters are shown by the next `draw_char`.
+pub fn set_blink_visible(visible: bool) {
+    BLINK_VISIBLE.store(visible, Ordering::Relaxed);
+}
 
-/// A structure representing the VGA text buffer.
-#[repr(transparent)]
-struct Buffer {
-    pixels: [[u8; BUFFER_WIDTH]; BUFFER_HEIGHT],
+pub fn blink_visible() -> bool {
+    BLINK_VISIBLE.load(Ordering::Relaxed)
 }
 
+/// The default mouse cursor: an arrow drawn with `#` for its outline and `o` for its inside.
+const ARROW: [&str; 12] = [
+    "#       ",
+    "##      ",
+    "#o#     ",
+    "#oo#    ",
+    "#ooo#   ",
+    "#oooo#  ",
+    "#ooooo# ",
+    "#oooooo#",
+    "#ooo####",
+    "#o#o#   ",
+    "## #o#  ",
+    "    ##  ",
+];
+
+fn arrow_cursor() -> Bitmap {
+    // anything that isn't a named color works as the key.
+    let transparent = 255;
+    let mut data = Vec::with_capacity(ARROW.len() * ARROW[0].len());
+    for row in ARROW.iter() {
+        for c in row.bytes() {
+            data.push(match c {
+                b'#' => Color::Black as u8,
+                b'o' => Color::White as u8,
+                _ => transparent,
+            });
+        }
+    }
+    Bitmap::new(ARROW[0].len(), ARROW.len(), data).with_color_key(transparent)
+}
+
+/// Where and how the screen's pixels are laid out in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub address: usize,
+    pub width: usize,
+    pub height: usize,
+    /// The number of bytes from the start of one row to the next.
+    pub stride: usize,
+    /// 1 for palette indices, or 2, 3 or 4 for 16, 24 and 32-bit color.
+    pub bytes_per_pixel: usize,
+    /// For framebuffers holding two pages one after the other, shows the
+    /// page starting at the given row.
+    pub flip: Option<fn(usize)>,
+}
+
+impl Framebuffer {
+    /// The 320x200 framebuffer of VGA mode 13h.
+    pub fn mode13h() -> Framebuffer {
+        Framebuffer {
+            address: 0xa0000,
+            width: 320,
+            height: 200,
+            stride: 320,
+            bytes_per_pixel: 1,
+            flip: None,
+        }
+    }
+}
+
+
+/// A rectangle of pixels on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x.saturating_add(self.width)
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y.saturating_add(self.height)
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// Whether `other` lies entirely within this rectangle.
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.right() <= self.right() && other.y >= self.y && other.bottom() <= self.bottom()
+    }
+
+    /// The part of this rectangle that also lies within `other`, if any.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if x < right && y < bottom {
+            Some(Rect::new(x, y, right - x, bottom - y))
+        } else {
+            None
+        }
+    }
+
+    /// The smallest rectangle covering both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+}
+
+/// An error returned by the checked drawing functions, which draw nothing when they fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawError {
+    /// The shape would reach outside the screen or the clip rectangle.
+    OutOfBounds,
+    /// The bitmap holds fewer pixels than its width and height need.
+    InvalidBitmap,
+}
+
+impl fmt::Display for DrawError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DrawError::OutOfBounds => write!(f, "Out of bounds."),
+            DrawError::InvalidBitmap => write!(f, "Invalid bitmap."),
+        }
+    }
+}
 
 /// A writer type that allows writing ASCII bytes and strings to an underlying `Buffer`.
 ///
-/// Wraps lines at `BUFFER_WIDTH`. Supports newline characters and implements the
-/// `core::fmt::Write` trait.
+/// Every drawing function clips against the screen and the optional clip
+/// rectangle. The `try_` variants report shapes that don't fit instead.
 pub struct Writer {
-    vgabuffer: &'static mut Buffer,
-    buffer: [[u8; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    framebuffer: Framebuffer,
+    width: usize,
+    height: usize,
+    /// The back buffer, holding a palette index for each pixel.
+    buffer: Vec<u8>,
+    /// The span of each row, as `(start, end)`, changed since the last `apply`.
+    dirty: Vec<(usize, usize)>,
+    /// Drawing outside this rectangle is discarded.
+    clip: Option<Rect>,
+    /// The font `draw_char` uses.
+    font: &'static dyn Font,
+    /// The colors palette indices are shown as on framebuffers with more than 8 bits per pixel.
+    palette: Palette,
+    /// Whether `apply` waits for vertical retrace.
+    vsync: bool,
+    /// The first row of the page being shown, when flipping pages.
+    front: usize,
+    /// The spans changed in the frame before, which the hidden page is missing.
+    previous_dirty: Vec<(usize, usize)>,
+    /// Where the mouse cursor is drawn, if it's shown.
+    cursor: Option<(usize, usize)>,
+    cursor_sprite: Bitmap,
+    /// The area the cursor covered on screen when last shown.
+    cursor_drawn: Option<Rect>,
+    /// Whether drawing goes to a bitmap passed to `draw_on` rather than the screen.
+    offscreen: bool,
+    /// What was drawn on that bitmap.
+    offscreen_damage: Option<Rect>,
 }
 
 impl Writer {
+    pub fn new(framebuffer: Framebuffer) -> Writer {
+        let mut palette = Palette::new();
+        for color in Color::ALL.iter() {
+            palette.colors[*color as usize] = color.rgb();
+        }
+
+        let mut writer = Writer {
+            framebuffer: framebuffer,
+            width: 0,
+            height: 0,
+            buffer: Vec::new(),
+            dirty: Vec::new(),
+            clip: None,
+            font: &font::FONT8X8,
+            palette: palette,
+            vsync: false,
+            front: 0,
+            previous_dirty: Vec::new(),
+            cursor: None,
+            cursor_sprite: arrow_cursor(),
+            cursor_drawn: None,
+            offscreen: false,
+            offscreen_damage: None,
+        };
+        writer.set_framebuffer(framebuffer);
+        writer
+    }
+
+    /// Switches to drawing on another framebuffer, such as after a mode change.
+    /// The back buffer is resized and cleared.
+    pub fn set_framebuffer(&mut self, framebuffer: Framebuffer) {
+        self.framebuffer = framebuffer;
+        self.width = framebuffer.width;
+        self.height = framebuffer.height;
+        self.buffer = alloc::vec![Color::Black as u8; self.width * self.height];
+        self.dirty = alloc::vec![(0, 0); self.height];
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+        self.previous_dirty = self.dirty.clone();
+        self.front = 0;
+        self.cursor_drawn = None;
+    }
+
+    pub fn framebuffer(&self) -> Framebuffer {
+        self.framebuffer
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.vsync = vsync;
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.vsync
+    }
+
+    /// Moves the mouse cursor, or hides it with `None`. It's drawn over
+    /// everything else by `apply`, without changing the back buffer.
+    pub fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        self.cursor = position;
+    }
+
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+
+    /// Changes the cursor's picture. Its top left corner is the hotspot.
+    pub fn set_cursor_sprite(&mut self, sprite: Bitmap) {
+        self.cursor_sprite = sprite;
+        if let Some(area) = self.cursor_drawn {
+            self.mark_dirty(area);
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Sets the colors used on framebuffers with more than 8 bits per pixel,
+    /// where there is no hardware palette.
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+    }
+
+    /// Marks an area of the back buffer as changed, so `apply` copies it.
+    ///
+    /// The area must already be clipped to the screen.
+    fn mark_dirty(&mut self, area: Rect) {
+        if self.offscreen {
+            self.offscreen_damage = Some(self.offscreen_damage.map_or(area, |damage| damage.union(&area)));
+            return;
+        }
+
+        for row in area.y..area.bottom() {
+            let (start, end) = self.dirty[row];
+            if start < end {
+                self.dirty[row] = (start.min(area.x), end.max(area.right()));
+            } else {
+                self.dirty[row] = (area.x, area.right());
+            }
+        }
+    }
+
+    /// Runs `draw` with the bitmap standing in for the back buffer, so anything
+    /// the writer can draw on screen can be drawn on a bitmap. Returns the area
+    /// of the bitmap that was drawn on.
+    ///
+    /// The clip rectangle is cleared while drawing. `draw` mustn't change the
+    /// framebuffer or call `apply`. A bitmap with less data than its size
+    /// says is only drawn on as far as its data goes.
+    pub fn draw_on<R, F: FnOnce(&mut Writer) -> R>(&mut self, surface: &mut Bitmap, draw: F) -> (R, Option<Rect>) {
+        let (width, height, clip) = (self.width, self.height, self.clip.take());
+        core::mem::swap(&mut self.buffer, &mut surface.data);
+        self.width = surface.width;
+        self.height = match surface.width {
+            0 => 0,
+            columns => surface.height.min(self.buffer.len() / columns),
+        };
+        self.offscreen = true;
+
+        let result = draw(self);
+
+        core::mem::swap(&mut self.buffer, &mut surface.data);
+        self.width = width;
+        self.height = height;
+        self.clip = clip;
+        self.offscreen = false;
+        (result, self.offscreen_damage.take())
+    }
+
+    pub fn set_clip(&mut self, clip: Option<Rect>) {
+        self.clip = clip;
+    }
+
+    pub fn clip(&self) -> Option<Rect> {
+        self.clip
+    }
+
+    pub fn set_font(&mut self, font: &'static dyn Font) {
+        self.font = font;
+    }
+
+    pub fn font(&self) -> &'static dyn Font {
+        self.font
+    }
+
+    /// The area that can be drawn to: the screen, narrowed by the clip rectangle.
+    pub fn visible(&self) -> Rect {
+        let screen = Rect::new(0, 0, self.width, self.height);
+        match self.clip {
+            Some(clip) => screen.intersect(&clip).unwrap_or(Rect::new(0, 0, 0, 0)),
+            None => screen,
+        }
+    }
+
+    fn check(&self, area: Rect) -> Result<(), DrawError> {
+        if self.visible().contains_rect(&area) {
+            Ok(())
+        } else {
+            Err(DrawError::OutOfBounds)
+        }
+    }
+
     pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
-        self.buffer[y][x] = color as u8;
+        self.write_raw_pixel(x, y, color as u8);
     }
 
     pub fn write_raw_pixel(&mut self, x: usize, y: usize, color: u8) {
-        self.buffer[y][x] = color;
+        if self.visible().contains(x, y) {
+            self.buffer[y * self.width + x] = color;
+            self.mark_dirty(Rect::new(x, y, 1, 1));
+        }
     }
 
+    pub fn try_write_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), DrawError> {
+        self.check(Rect::new(x, y, 1, 1))?;
+        self.write_pixel(x, y, color);
+        Ok(())
+    }
+
+    /// Copies an area of the back buffer. Pixels beyond the screen are captured as 0.
     pub fn capture_bmp(&mut self, x: usize, y: usize, width: usize, height: usize) -> Bitmap {
         let mut capture = Bitmap::new(width, height, Vec::new());
-        for _y in y..(y+height) {
-            for _x in x..(x+width) {
-                capture.data.push(self.buffer[_y][_x] as u8);
+        for _y in y..y.saturating_add(height) {
+            for _x in x..x.saturating_add(width) {
+                if _y < self.height && _x < self.width {
+                    capture.data.push(self.buffer[_y * self.width + _x] as u8);
+                } else {
+                    capture.data.push(0);
+                }
             }
         }
         return capture;
     }
 
+    pub fn try_capture_bmp(&mut self, x: usize, y: usize, width: usize, height: usize) -> Result<Bitmap, DrawError> {
+        let screen = Rect::new(0, 0, self.width, self.height);
+        if !screen.contains_rect(&Rect::new(x, y, width, height)) {
+            return Err(DrawError::OutOfBounds);
+        }
+        Ok(self.capture_bmp(x, y, width, height))
+    }
+
     pub fn draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let area = match Rect::new(x, y, width, height).intersect(&self.visible()) {
+            Some(area) => area,
+            None => return,
+        };
+
         let c = color as u8;
-        for _y in y..(y+height) {
-            for _x in x..(x+width) {
-                self.buffer[_y][_x] = c;
+        for _y in area.y..area.bottom() {
+            for _x in area.x..area.right() {
+                self.buffer[_y * self.width + _x] = c;
             }
         }
+        self.mark_dirty(area);
     }
 
+    pub fn try_draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) -> Result<(), DrawError> {
+        self.check(Rect::new(x, y, width, height))?;
+        self.draw_rect(x, y, width, height, color);
+        Ok(())
+    }
+
+    /// Fills the area that can be drawn to with a color.
     pub fn fill_buffer(&mut self, color: Color) {
-        self.buffer = [[color as u8; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        if self.clip.is_none() {
+            for pixel in self.buffer.iter_mut() {
+                *pixel = color as u8;
+            }
+            self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+        } else {
+            self.draw_rect(0, 0, self.width, self.height, color);
+        }
     }
 
+    /// Draws a bitmap, skipping pixels that match its color key.
     pub fn draw_bmp(&mut self, x: usize, y: usize, bmp: &Bitmap) {
-        for _y in 0..(bmp.height) {
-            for _x in 0..(bmp.width) {
-                if bmp.data[(_y * bmp.width + _x)] > 0 {
-                    self.buffer[y + _y][x + _x] = bmp.data[(_y * bmp.width + _x)] as u8;
+        self.blend_bmp(x, y, bmp, BlendMode::ColorKey);
+    }
+
+    pub fn blend_bmp(&mut self, x: usize, y: usize, bmp: &Bitmap, mode: BlendMode) {
+        let area = match Rect::new(x, y, bmp.width, bmp.height).intersect(&self.visible()) {
+            Some(area) => area,
+            None => return,
+        };
+
+        self.mark_dirty(area);
+        for _y in area.y..area.bottom() {
+            for _x in area.x..area.right() {
+                let value = match bmp.data.get((_y - y) * bmp.width + (_x - x)) {
+                    Some(&value) => value,
+                    None => continue,
+                };
+
+                let keyed = bmp.color_key == Some(value);
+                match mode {
+                    BlendMode::Opaque => self.buffer[_y * self.width + _x] = value,
+                    BlendMode::ColorKey if !keyed => self.buffer[_y * self.width + _x] = value,
+                    BlendMode::Lookup(table) if !keyed => self.buffer[_y * self.width + _x] = table[value as usize],
+                    BlendMode::Shade(table) if !keyed => {
+                        self.buffer[_y * self.width + _x] = table[self.buffer[_y * self.width + _x] as usize];
+                    },
+                    _ => {},
                 }
             }
         }
     }
 
+    pub fn try_draw_bmp(&mut self, x: usize, y: usize, bmp: &Bitmap) -> Result<(), DrawError> {
+        if bmp.data.len() < bmp.width * bmp.height {
+            return Err(DrawError::InvalidBitmap);
+        }
+        self.check(Rect::new(x, y, bmp.width, bmp.height))?;
+        self.draw_bmp(x, y, bmp);
+        Ok(())
+    }
+
     pub fn draw_char(&mut self, x: usize, y: usize, screen_char: ScreenChar) {
-        if let Some(glyph) = BASIC_FONTS.get(screen_char.character) {
-            let mut _x = 0;
-            let mut _y = 0;
-            for g in &glyph {
-                for bit in 0..8 {
-                    match *g & 1 << bit {
-                        0 => self.write_pixel(x + _x, y + _y, Color::Black),
-                        _ => self.write_pixel(x + _x, y + _y, screen_char.color),
+        let attributes = screen_char.attributes;
+        let (mut fg, bg) = if attributes.inverse {
+            (screen_char.background, screen_char.color)
+        } else {
+            (screen_char.color, screen_char.background)
+        };
+        if attributes.blink && !BLINK_VISIBLE.load(Ordering::Relaxed) {
+            fg = bg;
+        }
+
+        let height = self.font.height();
+        let area = match Rect::new(x, y, self.font.width(), height).intersect(&self.visible()) {
+            Some(area) => area,
+            None => return,
+        };
+
+        let glyph = self.font.glyph_or_fallback(screen_char.character);
+        self.mark_dirty(area);
+        for _y in area.y..area.bottom() {
+            let underline = attributes.underline && _y - y == height - 1;
+            for _x in area.x..area.right() {
+                let color = if underline || glyph.pixel(_x - x, _y - y) { fg } else { bg };
+                self.buffer[_y * self.width + _x] = color as u8;
+            }
+        }
+    }
+
+    pub fn try_draw_char(&mut self, x: usize, y: usize, screen_char: ScreenChar) -> Result<(), DrawError> {
+        self.check(Rect::new(x, y, self.font.width(), self.font.height()))?;
+        self.draw_char(x, y, screen_char);
+        Ok(())
+    }
+
+    /// Sets a single pixel, taking signed coordinates so shapes can hang off any edge.
+    fn plot(&mut self, x: isize, y: isize, color: u8) {
+        if x >= 0 && y >= 0 {
+            self.write_raw_pixel(x as usize, y as usize, color);
+        }
+    }
+
+    /// Fills the pixels from `x0` to `x1` inclusive on row `y`.
+    fn hline(&mut self, x0: isize, x1: isize, y: isize, color: u8) {
+        let visible = self.visible();
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        if y < visible.y as isize || y >= visible.bottom() as isize {
+            return;
+        }
+
+        let start = x0.max(visible.x as isize);
+        let end = x1.min(visible.right() as isize - 1);
+        if start > end {
+            return;
+        }
+
+        let y = y as usize;
+        for x in start as usize..=end as usize {
+            self.buffer[y * self.width + x] = color;
+        }
+        self.mark_dirty(Rect::new(start as usize, y, (end - start) as usize + 1, 1));
+    }
+
+    /// Draws a line between two points with Bresenham's algorithm.
+    pub fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: Color) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            self.plot(x, y, color as u8);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err = err + dy;
+                x = x + sx;
+            }
+            if e2 <= dx {
+                err = err + dx;
+                y = y + sy;
+            }
+        }
+    }
+
+    pub fn draw_circle(&mut self, cx: isize, cy: isize, r: isize, color: Color) {
+        let c = color as u8;
+        let mut x = r;
+        let mut y = 0;
+        let mut err = 1 - r;
+
+        while x >= y {
+            for &(px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)].iter() {
+                self.plot(cx + px, cy + py, c);
+            }
+            y = y + 1;
+            if err < 0 {
+                err = err + 2 * y + 1;
+            } else {
+                x = x - 1;
+                err = err + 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, cx: isize, cy: isize, r: isize, color: Color) {
+        self.fill_ellipse(cx, cy, r, r, color);
+    }
+
+    /// Draws the outline of an axis-aligned ellipse with the midpoint algorithm.
+    pub fn draw_ellipse(&mut self, cx: isize, cy: isize, rx: isize, ry: isize, color: Color) {
+        let c = color as u8;
+        let (rx2, ry2) = (rx * rx, ry * ry);
+        let mut x = 0;
+        let mut y = ry;
+        let mut px = 0;
+        let mut py = 2 * rx2 * y;
+
+        // the upper part of each quadrant, where the slope is shallow.
+        let mut p = ry2 - rx2 * ry + rx2 / 4;
+        while px < py {
+            self.plot_quadrants(cx, cy, x, y, c);
+            x = x + 1;
+            px = px + 2 * ry2;
+            if p < 0 {
+                p = p + ry2 + px;
+            } else {
+                y = y - 1;
+                py = py - 2 * rx2;
+                p = p + ry2 + px - py;
+            }
+        }
+
+        // the lower part, where the slope is steep.
+        p = ry2 * (2 * x + 1) * (2 * x + 1) / 4 + rx2 * (y - 1) * (y - 1) - rx2 * ry2;
+        while y >= 0 {
+            self.plot_quadrants(cx, cy, x, y, c);
+            y = y - 1;
+            py = py - 2 * rx2;
+            if p > 0 {
+                p = p + rx2 - py;
+            } else {
+                x = x + 1;
+                px = px + 2 * ry2;
+                p = p + rx2 - py + px;
+            }
+        }
+    }
+
+    fn plot_quadrants(&mut self, cx: isize, cy: isize, x: isize, y: isize, color: u8) {
+        self.plot(cx + x, cy + y, color);
+        self.plot(cx - x, cy + y, color);
+        self.plot(cx + x, cy - y, color);
+        self.plot(cx - x, cy - y, color);
+    }
+
+    pub fn fill_ellipse(&mut self, cx: isize, cy: isize, rx: isize, ry: isize, color: Color) {
+        if ry <= 0 {
+            self.hline(cx - rx, cx + rx, cy, color as u8);
+            return;
+        }
+
+        let (rx2, ry2) = (rx * rx, ry * ry);
+        for dy in -ry..=ry {
+            let dx = isqrt(rx2 * (ry2 - dy * dy) / ry2);
+            self.hline(cx - dx, cx + dx, cy + dy, color as u8);
+        }
+    }
+
+    pub fn draw_triangle(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, x2: isize, y2: isize, color: Color) {
+        self.draw_polygon(&[(x0, y0), (x1, y1), (x2, y2)], color);
+    }
+
+    pub fn fill_triangle(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, x2: isize, y2: isize, color: Color) {
+        self.fill_polygon(&[(x0, y0), (x1, y1), (x2, y2)], color);
+    }
+
+    /// Draws the closed outline through the given points.
+    pub fn draw_polygon(&mut self, points: &[(isize, isize)], color: Color) {
+        for i in 0..points.len() {
+            let (x0, y0) = points[i];
+            let (x1, y1) = points[(i + 1) % points.len()];
+            self.draw_line(x0, y0, x1, y1, color);
+        }
+    }
+
+    /// Fills a polygon one scanline at a time, using the even-odd rule.
+    pub fn fill_polygon(&mut self, points: &[(isize, isize)], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+
+        let visible = self.visible();
+        let top = points.iter().map(|p| p.1).min().unwrap().max(visible.y as isize);
+        let bottom = points.iter().map(|p| p.1).max().unwrap().min(visible.bottom() as isize - 1);
+        let mut crossings: Vec<isize> = Vec::new();
+
+        for y in top..=bottom {
+            crossings.clear();
+            for i in 0..points.len() {
+                let (x0, y0) = points[i];
+                let (x1, y1) = points[(i + 1) % points.len()];
+                // half-open, so a vertex shared by two edges only counts once.
+                if (y0 <= y && y < y1) || (y1 <= y && y < y0) {
+                    crossings.push(x0 + (y - y0) * (x1 - x0) / (y1 - y0));
+                }
+            }
+            crossings.sort_unstable();
+
+            for pair in crossings.chunks(2) {
+                if pair.len() == 2 {
+                    self.hline(pair[0], pair[1], y, color as u8);
+                }
+            }
+        }
+
+        // the scanlines stop short of the bottom edges, so trace the outline as well.
+        self.draw_polygon(points, color);
+    }
+
+    /// Recolors the area of same-colored pixels around a point.
+    pub fn flood_fill(&mut self, x: usize, y: usize, color: Color) {
+        let visible = self.visible();
+        if !visible.contains(x, y) {
+            return;
+        }
+
+        let target = self.buffer[y * self.width + x];
+        let c = color as u8;
+        if target == c {
+            return;
+        }
+
+        let mut stack = alloc::vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            if self.buffer[y * self.width + x] != target {
+                continue;
+            }
+
+            let mut left = x;
+            while left > visible.x && self.buffer[y * self.width + left - 1] == target {
+                left = left - 1;
+            }
+            let mut right = x;
+            while right + 1 < visible.right() && self.buffer[y * self.width + right + 1] == target {
+                right = right + 1;
+            }
+
+            for px in left..=right {
+                self.buffer[y * self.width + px] = c;
+            }
+            self.mark_dirty(Rect::new(left, y, right - left + 1, 1));
+
+            // queue one pixel of each run of the target color above and below.
+            for &ny in [y.wrapping_sub(1), y + 1].iter() {
+                if ny < visible.y || ny >= visible.bottom() {
+                    continue;
+                }
+                let mut px = left;
+                while px <= right {
+                    if self.buffer[ny * self.width + px] == target {
+                        stack.push((px, ny));
+                        while px <= right && self.buffer[ny * self.width + px] == target {
+                            px = px + 1;
+                        }
+                    } else {
+                        px = px + 1;
                     }
-                    _x = _x + 1;
                 }
-                _x = 0;
-                _y = _y + 1;
             }
         }
     }
 
+    /// Copies the parts of the back buffer changed since the last call to the screen.
+    ///
+    /// Framebuffers that can flip pages are drawn into the hidden page, which
+    /// is then shown. With vsync on, the copy or flip waits for vertical retrace
+    /// so it doesn't tear.
+    ///
+    /// Without the `graphics` feature the screen is in text mode, so the
+    /// back buffer is kept but never shown.
     pub fn apply(&mut self) {
-        self.vgabuffer.pixels = self.buffer.clone();
-    } 
+        if cfg!(not(feature = "graphics")) {
+            return;
+        }
+
+        // the cursor is drawn into the back buffer just for the copy, then
+        // what was under it is put back.
+        if let Some(area) = self.cursor_drawn.take() {
+            self.mark_dirty(area);
+        }
+        let under = self.draw_cursor();
+
+        let flip = self.framebuffer.flip;
+        let page = if flip.is_some() { self.height - self.front } else { 0 };
+        if flip.is_none() && self.vsync {
+            palette::wait_for_retrace();
+        }
+
+        for y in 0..self.height {
+            let (mut start, mut end) = self.dirty[y];
+            if flip.is_some() {
+                let (previous_start, previous_end) = self.previous_dirty[y];
+                if start >= end {
+                    start = previous_start;
+                    end = previous_end;
+                } else if previous_start < previous_end {
+                    start = start.min(previous_start);
+                    end = end.max(previous_end);
+                }
+                self.previous_dirty[y] = self.dirty[y];
+            }
+            self.dirty[y] = (0, 0);
+
+            if start < end {
+                self.copy_span(page + y, y, start, end.min(self.width));
+            }
+        }
+
+        if let Some((area, pixels)) = under {
+            for (row, y) in (area.y..area.bottom()).enumerate() {
+                let start = y * self.width + area.x;
+                self.buffer[start..start + area.width].copy_from_slice(&pixels[row * area.width..(row + 1) * area.width]);
+            }
+            self.cursor_drawn = Some(area);
+        }
+
+        if let Some(flip) = flip {
+            if self.vsync {
+                palette::wait_for_retrace();
+            }
+            flip(page);
+            self.front = page;
+        }
+    }
+
+    /// Draws the cursor sprite into the back buffer, returning the area it
+    /// covers and the pixels that were there.
+    fn draw_cursor(&mut self) -> Option<(Rect, Vec<u8>)> {
+        let (x, y) = self.cursor?;
+        let sprite = &self.cursor_sprite;
+        let area = Rect::new(x, y, sprite.width, sprite.height).intersect(&Rect::new(0, 0, self.width, self.height))?;
+
+        let mut under = Vec::with_capacity(area.width * area.height);
+        for _y in area.y..area.bottom() {
+            for _x in area.x..area.right() {
+                let pixel = &mut self.buffer[_y * self.width + _x];
+                under.push(*pixel);
+                match sprite.data.get((_y - y) * sprite.width + (_x - x)) {
+                    Some(&value) if sprite.color_key != Some(value) => *pixel = value,
+                    _ => {},
+                }
+            }
+        }
+        self.mark_dirty(area);
+        Some((area, under))
+    }
+
+    /// Copies part of a row of the back buffer to a row of the framebuffer,
+    /// converting palette indices to colors if it needs them.
+    fn copy_span(&self, row: usize, y: usize, start: usize, end: usize) {
+        let Framebuffer { address, stride, bytes_per_pixel, .. } = self.framebuffer;
+        let span = &self.buffer[y * self.width + start..y * self.width + end];
+        let out = (address + row * stride + start * bytes_per_pixel) as *mut u8;
+
+        unsafe {
+            if bytes_per_pixel == 1 {
+                core::ptr::copy_nonoverlapping(span.as_ptr(), out, span.len());
+                return;
+            }
+
+            for (i, index) in span.iter().enumerate() {
+                let [r, g, b] = self.palette.colors[*index as usize];
+                let pixel = out.add(i * bytes_per_pixel);
+                match bytes_per_pixel {
+                    2 => {
+                        let rgb565 = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+                        (pixel as *mut u16).write_unaligned(rgb565);
+                    },
+                    3 => {
+                        pixel.write(b);
+                        pixel.add(1).write(g);
+                        pixel.add(2).write(r);
+                    },
+                    _ => (pixel as *mut u32).write_unaligned((r as u32) << 16 | (g as u32) << 8 | b as u32),
+                }
+            }
+        }
+    }
 
     /// Writes the given ASCII string to the buffer.
     ///
@@ -186,6 +1117,21 @@ impl Writer {
     }
 }
 
+/// The integer square root of `n`, rounded down.
+fn isqrt(n: isize) -> isize {
+    if n <= 0 {
+        return 0;
+    }
+
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
 #[macro_export]
 macro_rules! pixel {
     ($x:expr, $y:expr, $color:expr) => ($crate::vga_buffer::_pixel($x, $y, $color));
@@ -212,11 +1158,83 @@ macro_rules! bmp {
     ($x:expr, $y:expr, $bmp:expr) => ($crate::vga_buffer::_bmp($x, $y, $bmp));
 }
 
+#[macro_export]
+macro_rules! blend_bmp {
+    ($x:expr, $y:expr, $bmp:expr, $mode:expr) => ($crate::vga_buffer::_blend_bmp($x, $y, $bmp, $mode));
+}
+
 #[macro_export]
 macro_rules! draw_char {
     ($x:expr, $y:expr, $chr:expr) => ($crate::vga_buffer::_char($x, $y, $chr));
 }
 
+#[macro_export]
+macro_rules! draw_line {
+    ($x0:expr, $y0:expr, $x1:expr, $y1:expr, $color:expr) => ($crate::vga_buffer::_draw_line($x0, $y0, $x1, $y1, $color));
+}
+
+#[macro_export]
+macro_rules! circle {
+    ($x:expr, $y:expr, $r:expr, $color:expr) => ($crate::vga_buffer::_circle($x, $y, $r, $color));
+}
+
+#[macro_export]
+macro_rules! fill_circle {
+    ($x:expr, $y:expr, $r:expr, $color:expr) => ($crate::vga_buffer::_fill_circle($x, $y, $r, $color));
+}
+
+#[macro_export]
+macro_rules! ellipse {
+    ($x:expr, $y:expr, $rx:expr, $ry:expr, $color:expr) => ($crate::vga_buffer::_ellipse($x, $y, $rx, $ry, $color));
+}
+
+#[macro_export]
+macro_rules! fill_ellipse {
+    ($x:expr, $y:expr, $rx:expr, $ry:expr, $color:expr) => ($crate::vga_buffer::_fill_ellipse($x, $y, $rx, $ry, $color));
+}
+
+#[macro_export]
+macro_rules! triangle {
+    ($x0:expr, $y0:expr, $x1:expr, $y1:expr, $x2:expr, $y2:expr, $color:expr) => (
+        $crate::vga_buffer::_triangle($x0, $y0, $x1, $y1, $x2, $y2, $color)
+    );
+}
+
+#[macro_export]
+macro_rules! fill_triangle {
+    ($x0:expr, $y0:expr, $x1:expr, $y1:expr, $x2:expr, $y2:expr, $color:expr) => (
+        $crate::vga_buffer::_fill_triangle($x0, $y0, $x1, $y1, $x2, $y2, $color)
+    );
+}
+
+#[macro_export]
+macro_rules! polygon {
+    ($points:expr, $color:expr) => ($crate::vga_buffer::_polygon($points, $color));
+}
+
+#[macro_export]
+macro_rules! fill_polygon {
+    ($points:expr, $color:expr) => ($crate::vga_buffer::_fill_polygon($points, $color));
+}
+
+#[macro_export]
+macro_rules! flood_fill {
+    ($x:expr, $y:expr, $color:expr) => ($crate::vga_buffer::_flood_fill($x, $y, $color));
+}
+
+/// Switches the font used to draw characters.
+#[macro_export]
+macro_rules! set_font {
+    ($font:expr) => ($crate::vga_buffer::_set_font($font));
+}
+
+/// Restricts drawing to a rectangle, or lifts the restriction when given no arguments.
+#[macro_export]
+macro_rules! clip {
+    () => ($crate::vga_buffer::_clip(None));
+    ($x:expr, $y:expr, $w:expr, $h:expr) => ($crate::vga_buffer::_clip(Some($crate::vga_buffer::Rect::new($x, $y, $w, $h))));
+}
+
 #[macro_export]
 macro_rules! fill_buffer {
     ($color:expr) => ($crate::vga_buffer::_fill_buffer($color));
@@ -251,10 +1269,388 @@ pub fn _char(x: usize, y: usize, chr: ScreenChar) {
     WRITER.lock().draw_char(x, y, chr);
 }
 
+pub fn _blend_bmp(x: usize, y: usize, bmp: &Bitmap, mode: BlendMode) {
+    WRITER.lock().blend_bmp(x, y, bmp, mode);
+}
+
+pub fn _draw_line(x0: isize, y0: isize, x1: isize, y1: isize, color: Color) {
+    WRITER.lock().draw_line(x0, y0, x1, y1, color);
+}
+
+pub fn _circle(x: isize, y: isize, r: isize, color: Color) {
+    WRITER.lock().draw_circle(x, y, r, color);
+}
+
+pub fn _fill_circle(x: isize, y: isize, r: isize, color: Color) {
+    WRITER.lock().fill_circle(x, y, r, color);
+}
+
+pub fn _ellipse(x: isize, y: isize, rx: isize, ry: isize, color: Color) {
+    WRITER.lock().draw_ellipse(x, y, rx, ry, color);
+}
+
+pub fn _fill_ellipse(x: isize, y: isize, rx: isize, ry: isize, color: Color) {
+    WRITER.lock().fill_ellipse(x, y, rx, ry, color);
+}
+
+pub fn _triangle(x0: isize, y0: isize, x1: isize, y1: isize, x2: isize, y2: isize, color: Color) {
+    WRITER.lock().draw_triangle(x0, y0, x1, y1, x2, y2, color);
+}
+
+pub fn _fill_triangle(x0: isize, y0: isize, x1: isize, y1: isize, x2: isize, y2: isize, color: Color) {
+    WRITER.lock().fill_triangle(x0, y0, x1, y1, x2, y2, color);
+}
+
+pub fn _polygon(points: &[(isize, isize)], color: Color) {
+    WRITER.lock().draw_polygon(points, color);
+}
+
+pub fn _fill_polygon(points: &[(isize, isize)], color: Color) {
+    WRITER.lock().fill_polygon(points, color);
+}
+
+pub fn _flood_fill(x: usize, y: usize, color: Color) {
+    WRITER.lock().flood_fill(x, y, color);
+}
+
+pub fn _set_font(font: &'static dyn Font) {
+    WRITER.lock().set_font(font);
+}
+
+/// The font characters are currently drawn with.
+pub fn font() -> &'static dyn Font {
+    WRITER.lock().font()
+}
+
+/// The width and height of the screen in pixels.
+pub fn size() -> (usize, usize) {
+    let writer = WRITER.lock();
+    (writer.width(), writer.height())
+}
+
+pub fn _clip(clip: Option<Rect>) {
+    WRITER.lock().set_clip(clip);
+}
+
 pub fn _fill_buffer(color: Color) {
     WRITER.lock().fill_buffer(color);
 }
 
 pub fn _apply() {
     WRITER.lock().apply();
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_drawing_is_clipped() {
+    serial_print!("test_drawing_is_clipped...");
+    let mut writer = WRITER.lock();
+
+    // shapes reaching past the edge are cut off instead of panicking.
+    let (width, height) = (writer.width(), writer.height());
+    writer.draw_rect(width - 4, height - 4, 16, 16, Color::Red);
+    writer.draw_bmp(width - 1, 0, &Bitmap::new(4, 1, alloc::vec![1, 2, 3, 4]));
+    writer.draw_char(width - 4, height - 4, ScreenChar::new('A', Color::White));
+    assert_eq!(writer.capture_bmp(width - 1, 0, 2, 1).data, [1, 0]);
+
+    assert_eq!(writer.try_draw_rect(width - 4, 0, 8, 8, Color::Red), Err(DrawError::OutOfBounds));
+    assert_eq!(writer.try_draw_rect(0, 0, 12, 12, Color::Red), Ok(()));
+
+    writer.set_clip(Some(Rect::new(10, 10, 4, 4)));
+    writer.draw_rect(0, 0, 20, 20, Color::Blue);
+    assert_eq!(writer.try_write_pixel(9, 10, Color::Blue), Err(DrawError::OutOfBounds));
+    writer.set_clip(None);
+    assert_eq!(writer.capture_bmp(9, 10, 2, 1).data, [Color::Red as u8, Color::Blue as u8]);
+    serial_println!("[ok]");
+}
+
+/// Draws on a blank 9x9 surface, returning it as rows of `#` for drawn pixels and `.` for the rest.
+#[cfg(test)]
+fn sketch<F: FnOnce(&mut Writer)>(draw: F) -> Vec<alloc::string::String> {
+    let mut surface = Bitmap::new(9, 9, alloc::vec![0; 81]);
+    WRITER.lock().draw_on(&mut surface, draw);
+    surface.data.chunks(9)
+        .map(|row| row.iter().map(|&pixel| if pixel == 0 { '.' } else { '#' }).collect())
+        .collect()
+}
+
+#[test_case]
+fn test_draw_lines_and_outlines() {
+    serial_print!("test_draw_lines_and_outlines...");
+    // lines running off the surface are clipped, including at negative coordinates.
+    assert_eq!(sketch(|writer| {
+        writer.draw_line(0, 0, 8, 8, Color::Red);
+        writer.draw_line(-4, 2, 12, 2, Color::Red);
+    }), [
+        "#........",
+        ".#.......",
+        "#########",
+        "...#.....",
+        "....#....",
+        ".....#...",
+        "......#..",
+        ".......#.",
+        "........#",
+    ]);
+
+    assert_eq!(sketch(|writer| writer.draw_circle(4, 4, 3, Color::Red)), [
+        ".........",
+        "...###...",
+        "..#...#..",
+        ".#.....#.",
+        ".#.....#.",
+        ".#.....#.",
+        "..#...#..",
+        "...###...",
+        ".........",
+    ]);
+
+    assert_eq!(sketch(|writer| writer.draw_ellipse(4, 4, 4, 2, Color::Red)), [
+        ".........",
+        ".........",
+        "..#####..",
+        ".#.....#.",
+        "#.......#",
+        ".#.....#.",
+        "..#####..",
+        ".........",
+        ".........",
+    ]);
+
+    assert_eq!(sketch(|writer| writer.draw_triangle(0, 0, 8, 0, 0, 8, Color::Red)), [
+        "#########",
+        "#......#.",
+        "#.....#..",
+        "#....#...",
+        "#...#....",
+        "#..#.....",
+        "#.#......",
+        "##.......",
+        "#........",
+    ]);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_fill_shapes() {
+    serial_print!("test_fill_shapes...");
+    assert_eq!(sketch(|writer| writer.fill_circle(4, 4, 3, Color::Red)), [
+        ".........",
+        "....#....",
+        "..#####..",
+        "..#####..",
+        ".#######.",
+        "..#####..",
+        "..#####..",
+        "....#....",
+        ".........",
+    ]);
+
+    // a flat ellipse is a single line.
+    assert_eq!(sketch(|writer| writer.fill_ellipse(4, 4, 4, 0, Color::Red))[4], "#########");
+
+    // the bottom and right edges are filled too, and the rest is left alone.
+    assert_eq!(sketch(|writer| writer.fill_polygon(&[(1, 1), (6, 1), (6, 6), (1, 6)], Color::Red)), [
+        ".........",
+        ".######..",
+        ".######..",
+        ".######..",
+        ".######..",
+        ".######..",
+        ".######..",
+        ".........",
+        ".........",
+    ]);
+
+    // too few points to enclose anything.
+    assert!(sketch(|writer| writer.fill_polygon(&[(1, 1), (6, 6)], Color::Red)).iter().all(|row| row == "........."));
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_flood_fill() {
+    serial_print!("test_flood_fill...");
+    let mut writer = WRITER.lock();
+    let mut surface = Bitmap::new(9, 9, alloc::vec![0; 81]);
+
+    // an empty surface fills edge to edge.
+    let (_, damage) = writer.draw_on(&mut surface, |writer| writer.flood_fill(4, 4, Color::Green));
+    assert_eq!(damage, Some(Rect::new(0, 0, 9, 9)));
+    assert!(surface.data.iter().all(|&pixel| pixel == Color::Green as u8));
+
+    // filling with the color already there changes nothing.
+    let (_, damage) = writer.draw_on(&mut surface, |writer| writer.flood_fill(4, 4, Color::Green));
+    assert_eq!(damage, None);
+
+    // the fill stops at other colors, and reaches around corners.
+    let mut surface = Bitmap::new(9, 9, alloc::vec![0; 81]);
+    writer.draw_on(&mut surface, |writer| {
+        writer.draw_polygon(&[(2, 2), (6, 2), (6, 6), (2, 6)], Color::Red);
+        writer.draw_line(4, 2, 4, 4, Color::Red);
+        writer.flood_fill(3, 3, Color::Blue);
+    });
+    assert_eq!(surface.data[3 * 9 + 5], Color::Blue as u8);
+    assert_eq!(surface.data[5 * 9 + 3], Color::Blue as u8);
+    assert_eq!(surface.data[0], 0);
+    assert_eq!(surface.data[8 * 9 + 8], 0);
+
+    // a start point outside the clip does nothing, and the fill stays inside it.
+    let mut surface = Bitmap::new(9, 9, alloc::vec![0; 81]);
+    writer.draw_on(&mut surface, |writer| {
+        writer.set_clip(Some(Rect::new(0, 0, 4, 9)));
+        writer.flood_fill(6, 4, Color::Blue);
+        assert!(writer.buffer.iter().all(|&pixel| pixel == 0));
+        writer.flood_fill(1, 4, Color::Blue);
+    });
+    for row in surface.data.chunks(9) {
+        assert_eq!(row[..4], [Color::Blue as u8; 4]);
+        assert_eq!(row[4..], [0; 5]);
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_blend_opaque_and_color_key() {
+    serial_print!("test_blend_opaque_and_color_key...");
+    let mut writer = WRITER.lock();
+    let bmp = Bitmap::new(3, 1, alloc::vec![0, 5, 6]);
+
+    writer.draw_rect(0, 20, 3, 1, Color::Red);
+    writer.draw_bmp(0, 20, &bmp);
+    assert_eq!(writer.capture_bmp(0, 20, 3, 1).data, [0, 5, 6]);
+
+    writer.draw_rect(0, 20, 3, 1, Color::Red);
+    writer.draw_bmp(0, 20, &bmp.with_color_key(5));
+    assert_eq!(writer.capture_bmp(0, 20, 3, 1).data, [0, Color::Red as u8, 6]);
+
+    writer.draw_rect(0, 20, 3, 1, Color::Red);
+    writer.blend_bmp(0, 20, &Bitmap::new(3, 1, alloc::vec![0, 5, 6]).with_color_key(0), BlendMode::Opaque);
+    assert_eq!(writer.capture_bmp(0, 20, 3, 1).data, [0, 5, 6]);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_blend_lookup_and_shade() {
+    serial_print!("test_blend_lookup_and_shade...");
+    let mut writer = WRITER.lock();
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        *entry = (i as u8).wrapping_add(8);
+    }
+    let bmp = Bitmap::new(3, 1, alloc::vec![0, 1, 2]).with_color_key(0);
+
+    writer.draw_rect(0, 21, 3, 1, Color::Red);
+    writer.blend_bmp(0, 21, &bmp, BlendMode::Lookup(&table));
+    assert_eq!(writer.capture_bmp(0, 21, 3, 1).data, [Color::Red as u8, 9, 10]);
+
+    writer.draw_rect(0, 21, 3, 1, Color::Red);
+    writer.blend_bmp(0, 21, &bmp, BlendMode::Shade(&table));
+    assert_eq!(writer.capture_bmp(0, 21, 3, 1).data, [Color::Red as u8, Color::LightRed as u8, Color::LightRed as u8]);
+    serial_println!("[ok]");
+}
+
+#[cfg(feature = "graphics")]
+#[test_case]
+fn test_apply_to_framebuffer() {
+    serial_print!("test_apply_to_framebuffer...");
+    // two rows of three 32-bit pixels, each row padded to 16 bytes.
+    let mut memory = alloc::vec![0u8; 32];
+    let mut writer = Writer::new(Framebuffer {
+        address: memory.as_mut_ptr() as usize,
+        width: 3,
+        height: 2,
+        stride: 16,
+        bytes_per_pixel: 4,
+        flip: None,
+    });
+
+    writer.write_pixel(2, 1, Color::LightRed);
+    writer.apply();
+    assert_eq!(memory[16 + 8..16 + 12], [85, 85, 255, 0]);
+    assert_eq!(memory[12..16], [0, 0, 0, 0]);
+    serial_println!("[ok]");
+}
+
+#[cfg(all(test, feature = "graphics"))]
+static SHOWN_ROW: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(usize::max_value());
+
+#[cfg(feature = "graphics")]
+#[test_case]
+fn test_apply_flips_pages() {
+    serial_print!("test_apply_flips_pages...");
+    fn show_row(row: usize) {
+        SHOWN_ROW.store(row, Ordering::SeqCst);
+    }
+
+    // two pages of one row of two 8-bit pixels.
+    let mut memory = alloc::vec![0u8; 4];
+    let mut writer = Writer::new(Framebuffer {
+        address: memory.as_mut_ptr() as usize,
+        width: 2,
+        height: 1,
+        stride: 2,
+        bytes_per_pixel: 1,
+        flip: Some(show_row),
+    });
+    writer.write_pixel(0, 0, Color::Blue);
+    writer.apply();
+    assert_eq!(SHOWN_ROW.load(Ordering::SeqCst), 1);
+    assert_eq!(memory[2..4], [Color::Blue as u8, 0]);
+
+    // the other page is brought up to date with both frames.
+    writer.write_pixel(1, 0, Color::Green);
+    writer.apply();
+    assert_eq!(SHOWN_ROW.load(Ordering::SeqCst), 0);
+    assert_eq!(memory[0..2], [Color::Blue as u8, Color::Green as u8]);
+    serial_println!("[ok]");
+}
+
+#[cfg(feature = "graphics")]
+#[test_case]
+fn test_cursor_save_under() {
+    serial_print!("test_cursor_save_under...");
+    let mut memory = alloc::vec![0u8; 16 * 16];
+    let mut writer = Writer::new(Framebuffer {
+        address: memory.as_mut_ptr() as usize,
+        width: 16,
+        height: 16,
+        stride: 16,
+        bytes_per_pixel: 1,
+        flip: None,
+    });
+    writer.draw_rect(0, 0, 16, 16, Color::Blue);
+
+    // the cursor shows on screen but not in the back buffer.
+    writer.set_cursor(Some((1, 1)));
+    writer.apply();
+    assert_eq!(memory[16 + 1], Color::Black as u8);
+    assert_eq!(memory[3 * 16 + 2], Color::White as u8);
+    assert_eq!(writer.capture_bmp(1, 1, 1, 1).data, [Color::Blue as u8]);
+
+    writer.set_cursor(Some((8, 8)));
+    writer.apply();
+    assert_eq!(memory[16 + 1], Color::Blue as u8);
+    assert_eq!(memory[8 * 16 + 8], Color::Black as u8);
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn test_draw_on_bitmap() {
+    serial_print!("test_draw_on_bitmap...");
+    let mut writer = WRITER.lock();
+    let mut surface = Bitmap::new(4, 3, alloc::vec![0; 12]);
+    let before = writer.capture_bmp(1, 1, 2, 1).data;
+
+    let (_, damage) = writer.draw_on(&mut surface, |writer| writer.draw_rect(1, 1, 2, 1, Color::Green));
+    assert_eq!(damage, Some(Rect::new(1, 1, 2, 1)));
+    assert_eq!(surface.data[4..8], [0, Color::Green as u8, Color::Green as u8, 0]);
+
+    // the screen is left alone.
+    assert_eq!(writer.capture_bmp(1, 1, 2, 1).data, before);
+
+    // a bitmap missing data is drawn on as far as it goes.
+    let mut short = Bitmap::new(4, 3, alloc::vec![0; 6]);
+    let (_, damage) = writer.draw_on(&mut short, |writer| writer.draw_rect(0, 0, 4, 3, Color::Green));
+    assert_eq!(damage, Some(Rect::new(0, 0, 4, 1)));
+    assert_eq!(short.data, [Color::Green as u8, Color::Green as u8, Color::Green as u8, Color::Green as u8, 0, 0]);
+    serial_println!("[ok]");
+}
@@ -0,0 +1,400 @@
+use crate::{
+    mouse::{Buttons, MouseEvent},
+    vga_buffer::{Bitmap, Color, Rect, ScreenChar, Writer, WRITER},
+};
+use alloc::{string::String, vec::Vec};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+#[cfg(test)]
+use crate::{serial_print, serial_println};
+
+lazy_static! {
+    pub static ref WINDOWS: Mutex<WindowManager> = Mutex::new(WindowManager::new());
+}
+
+/// The width of the frame around a window.
+const BORDER: usize = 1;
+
+/// Identifies a window for as long as it's open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WindowId(pub usize);
+
+/// A window, which draws on its own surface and is shown by the compositor.
+pub struct Window {
+    pub id: WindowId,
+    pub title: String,
+    /// Where the surface is on screen. The frame and title bar sit around it.
+    pub x: usize,
+    pub y: usize,
+    pub surface: Bitmap,
+    /// Fill the screen behind every other window, without a frame.
+    pub maximized: bool,
+}
+
+impl Window {
+    /// The area the window covers on screen, frame included.
+    fn frame(&self, title_height: usize) -> Rect {
+        if self.maximized {
+            return Rect::new(self.x, self.y, self.surface.width, self.surface.height);
+        }
+        Rect::new(
+            self.x.saturating_sub(BORDER),
+            self.y.saturating_sub(BORDER + title_height),
+            self.surface.width + 2 * BORDER,
+            self.surface.height + 2 * BORDER + title_height,
+        )
+    }
+
+    fn title_bar(&self, title_height: usize) -> Rect {
+        if self.maximized {
+            return Rect::new(0, 0, 0, 0);
+        }
+        let frame = self.frame(title_height);
+        Rect::new(frame.x, frame.y, frame.width, title_height + BORDER)
+    }
+}
+
+/// Keeps the windows in stacking order and composites them onto the `Writer`'s back buffer.
+pub struct WindowManager {
+    /// Bottom to top.
+    windows: Vec<Window>,
+    next_id: usize,
+    focus: Option<WindowId>,
+    /// The part of the screen that needs compositing again.
+    damage: Option<Rect>,
+    /// The window being dragged by its title bar, and where it was grabbed
+    /// relative to the top left of its frame.
+    drag: Option<(WindowId, usize, usize)>,
+    buttons: Buttons,
+    /// Off while a command draws straight on the screen.
+    enabled: bool,
+    /// The color shown where there's no window.
+    pub desktop: Color,
+}
+
+impl WindowManager {
+    pub fn new() -> WindowManager {
+        WindowManager {
+            windows: Vec::new(),
+            next_id: 0,
+            focus: None,
+            damage: None,
+            drag: None,
+            buttons: Buttons::default(),
+            enabled: true,
+            desktop: Color::DarkGray,
+        }
+    }
+
+    fn damage(&mut self, area: Rect) {
+        self.damage = Some(self.damage.map_or(area, |damage| damage.union(&area)));
+    }
+
+    fn index(&self, id: WindowId) -> Option<usize> {
+        self.windows.iter().position(|window| window.id == id)
+    }
+
+    pub fn get(&self, id: WindowId) -> Option<&Window> {
+        self.windows.iter().find(|window| window.id == id)
+    }
+
+    /// The open windows, bottom to top.
+    pub fn windows(&self) -> &[Window] {
+        &self.windows
+    }
+
+    /// Opens a window on top of the others, with its surface cleared to black, and focuses it.
+    /// Returns `None` if the surface is too big to allocate.
+    pub fn open(&mut self, title: &str, x: usize, y: usize, width: usize, height: usize) -> Option<WindowId> {
+        let size = width.checked_mul(height)?;
+        let id = WindowId(self.next_id);
+        self.next_id = self.next_id + 1;
+        self.windows.push(Window {
+            id: id,
+            title: String::from(title),
+            x: x,
+            y: y,
+            surface: Bitmap::new(width, height, alloc::vec![Color::Black as u8; size]),
+            maximized: false,
+        });
+        self.focus(id);
+        Some(id)
+    }
+
+    /// Opens a window filling the screen behind the others.
+    pub fn open_maximized(&mut self, title: &str, width: usize, height: usize) -> Option<WindowId> {
+        let id = self.open(title, 0, 0, width, height)?;
+        let window = self.windows.pop().unwrap();
+        self.windows.insert(0, Window { maximized: true, ..window });
+        Some(id)
+    }
+
+    pub fn close(&mut self, id: WindowId, title_height: usize) -> bool {
+        let index = match self.index(id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let window = self.windows.remove(index);
+        self.damage(window.frame(title_height));
+        if self.focus == Some(id) {
+            self.focus = self.windows.last().map(|window| window.id);
+        }
+        if self.drag.map(|(dragged, _, _)| dragged) == Some(id) {
+            self.drag = None;
+        }
+        true
+    }
+
+    /// Focuses a window and raises it above the others. Maximized windows stay at the bottom.
+    pub fn focus(&mut self, id: WindowId) {
+        let index = match self.index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        self.focus = Some(id);
+        if !self.windows[index].maximized {
+            let window = self.windows.remove(index);
+            self.windows.push(window);
+        }
+        // title bars change color and the window may now cover others.
+        self.invalidate();
+    }
+
+    pub fn focused(&self) -> Option<WindowId> {
+        self.focus
+    }
+
+    /// Moves a window's surface to the given position on a screen of the given size.
+    pub fn move_to(&mut self, id: WindowId, x: usize, y: usize, title_height: usize, screen: (usize, usize)) {
+        if let Some(index) = self.index(id) {
+            let before = self.windows[index].frame(title_height);
+            // keep the title bar on screen so the window can be dragged back.
+            let width = self.windows[index].surface.width;
+            self.windows[index].x = x.min(screen.0.saturating_sub(width + BORDER)).max(BORDER);
+            self.windows[index].y = y.min(screen.1).max(BORDER + title_height);
+            let after = self.windows[index].frame(title_height);
+            self.damage(before.union(&after));
+        }
+    }
+
+    /// Draws on a window's surface with the writer, returning what `draw` returns.
+    pub fn draw<R, F: FnOnce(&mut Writer) -> R>(&mut self, writer: &mut Writer, id: WindowId, draw: F) -> Option<R> {
+        let index = self.index(id)?;
+        let (screen_width, screen_height) = (writer.width(), writer.height());
+        let window = &mut self.windows[index];
+
+        // maximized windows follow the screen size.
+        if window.maximized && (window.surface.width, window.surface.height) != (screen_width, screen_height) {
+            window.surface = Bitmap::new(screen_width, screen_height, alloc::vec![Color::Black as u8; screen_width * screen_height]);
+            self.damage(Rect::new(0, 0, screen_width, screen_height));
+        }
+
+        let window = &mut self.windows[index];
+        let (x, y) = (window.x, window.y);
+        let (result, damage) = writer.draw_on(&mut window.surface, draw);
+        if let Some(damage) = damage {
+            self.damage(Rect::new(x + damage.x, y + damage.y, damage.width, damage.height));
+        }
+        Some(result)
+    }
+
+    /// Marks the whole screen for compositing.
+    pub fn invalidate(&mut self) {
+        self.damage(Rect::new(0, 0, usize::max_value(), usize::max_value()));
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if enabled {
+            self.invalidate();
+        }
+    }
+
+    /// The topmost window with its frame under a point.
+    pub fn window_at(&self, x: usize, y: usize, title_height: usize) -> Option<WindowId> {
+        self.windows.iter().rev()
+            .find(|window| window.frame(title_height).contains(x, y))
+            .map(|window| window.id)
+    }
+
+    /// Clicking a window focuses it, and dragging its title bar moves it.
+    pub fn mouse_event(&mut self, event: MouseEvent, title_height: usize, screen: (usize, usize)) {
+        if !self.enabled {
+            return;
+        }
+        let pressed = event.buttons.left && !self.buttons.left;
+        self.buttons = event.buttons;
+
+        if !event.buttons.left {
+            self.drag = None;
+        } else if pressed {
+            if let Some(id) = self.window_at(event.x, event.y, title_height) {
+                self.focus(id);
+                let window = self.get(id).unwrap();
+                if window.title_bar(title_height).contains(event.x, event.y) {
+                    let frame = window.frame(title_height);
+                    self.drag = Some((id, event.x - frame.x, event.y - frame.y));
+                }
+            }
+        } else if let Some((id, grab_x, grab_y)) = self.drag {
+            // the grab is relative to the frame, which starts above and left of the surface.
+            let x = event.x.saturating_sub(grab_x) + BORDER;
+            let y = event.y.saturating_sub(grab_y) + BORDER + title_height;
+            self.move_to(id, x, y, title_height, screen);
+        }
+    }
+
+    /// Composites the windows over the part of the back buffer that changed.
+    pub fn compose(&mut self, writer: &mut Writer) {
+        if !self.enabled {
+            return;
+        }
+        let screen = Rect::new(0, 0, writer.width(), writer.height());
+        let damage = match self.damage.take().and_then(|damage| damage.intersect(&screen)) {
+            Some(damage) => damage,
+            None => return,
+        };
+
+        let clip = writer.clip();
+        writer.set_clip(Some(damage));
+        writer.fill_buffer(self.desktop);
+
+        let font = writer.font();
+        let title_height = font.height();
+        for window in self.windows.iter() {
+            if !window.maximized {
+                let frame = window.frame(title_height);
+                let focused = self.focus == Some(window.id);
+                let (background, foreground) = if focused {
+                    (Color::Blue, Color::White)
+                } else {
+                    (Color::LightGray, Color::Black)
+                };
+                writer.draw_rect(frame.x, frame.y, frame.width, frame.height, background);
+                for (i, c) in window.title.chars().take(window.surface.width / font.width()).enumerate() {
+                    writer.draw_char(window.x + i * font.width(), frame.y, ScreenChar::with_background(c, foreground, background));
+                }
+            }
+            writer.blend_bmp(window.x, window.y, &window.surface, crate::vga_buffer::BlendMode::Opaque);
+        }
+        writer.set_clip(clip);
+    }
+}
+
+/// The height of window title bars, which fit one line of the current font.
+fn title_height() -> usize {
+    crate::vga_buffer::font().height()
+}
+
+/// Opens a window on top of the others and focuses it. The window is made
+/// smaller if it wouldn't fit on screen with its frame and title bar.
+pub fn open(title: &str, x: usize, y: usize, width: usize, height: usize) -> Option<WindowId> {
+    let title_height = title_height();
+    let (screen_width, screen_height) = crate::vga_buffer::size();
+    let width = width.min(screen_width.saturating_sub(2 * BORDER)).max(1);
+    let height = height.min(screen_height.saturating_sub(2 * BORDER + title_height)).max(1);
+    let mut windows = WINDOWS.lock();
+    let id = windows.open(title, x, y, width, height)?;
+    windows.move_to(id, x, y, title_height, (screen_width, screen_height));
+    Some(id)
+}
+
+pub fn close(id: WindowId) -> bool {
+    let title_height = title_height();
+    WINDOWS.lock().close(id, title_height)
+}
+
+pub fn focus(id: WindowId) {
+    WINDOWS.lock().focus(id);
+}
+
+pub fn move_to(id: WindowId, x: usize, y: usize) {
+    let title_height = title_height();
+    let screen = crate::vga_buffer::size();
+    WINDOWS.lock().move_to(id, x, y, title_height, screen);
+}
+
+/// Draws on a window's surface, returning `None` if the window isn't open.
+pub fn draw<R, F: FnOnce(&mut Writer) -> R>(id: WindowId, draw: F) -> Option<R> {
+    let mut windows = WINDOWS.lock();
+    let mut writer = WRITER.lock();
+    windows.draw(&mut writer, id, draw)
+}
+
+/// Turns compositing off while a command draws on the whole screen, or back on.
+pub fn set_enabled(enabled: bool) {
+    WINDOWS.lock().set_enabled(enabled);
+}
+
+pub fn mouse_event(event: MouseEvent) {
+    let title_height = title_height();
+    let screen = crate::vga_buffer::size();
+    WINDOWS.lock().mouse_event(event, title_height, screen);
+    present();
+}
+
+/// Composites what changed and shows it.
+pub fn present() {
+    let mut windows = WINDOWS.lock();
+    let mut writer = WRITER.lock();
+    windows.compose(&mut writer);
+    writer.apply();
+}
+
+#[test_case]
+fn test_compose_in_z_order() {
+    serial_print!("test_compose_in_z_order...");
+    let mut writer = WRITER.lock();
+    let mut manager = WindowManager::new();
+    let screen = (writer.width(), writer.height());
+
+    let below = manager.open("below", 20, 30, 10, 10).unwrap();
+    manager.draw(&mut writer, below, |writer| writer.fill_buffer(Color::Red));
+    let above = manager.open("above", 25, 30, 10, 10).unwrap();
+    manager.draw(&mut writer, above, |writer| writer.fill_buffer(Color::Green));
+    manager.compose(&mut writer);
+    assert_eq!(writer.capture_bmp(22, 32, 1, 1).data, [Color::Red as u8]);
+    assert_eq!(writer.capture_bmp(27, 32, 1, 1).data, [Color::Green as u8]);
+    assert_eq!(manager.window_at(27, 32, 8), Some(above));
+
+    // focusing the lower window raises it.
+    manager.focus(below);
+    manager.compose(&mut writer);
+    assert_eq!(writer.capture_bmp(27, 32, 1, 1).data, [Color::Red as u8]);
+    assert_eq!(manager.windows().last().map(|window| window.id), Some(below));
+
+    // dragging the title bar moves the window as far as the mouse, without a jump.
+    let drag = |x, y, left| MouseEvent {
+        x: x,
+        y: y,
+        previous_x: x,
+        previous_y: y,
+        buttons: Buttons { left: left, ..Buttons::default() },
+    };
+    manager.mouse_event(drag(33, 24, true), 8, screen);
+    manager.mouse_event(drag(33, 24, true), 8, screen);
+    assert_eq!(manager.get(above).map(|window| (window.x, window.y)), Some((25, 30)));
+    manager.mouse_event(drag(43, 44, true), 8, screen);
+    assert_eq!(manager.get(above).map(|window| (window.x, window.y)), Some((35, 50)));
+    manager.mouse_event(drag(43, 44, false), 8, screen);
+    manager.mouse_event(drag(20, 20, false), 8, screen);
+    assert_eq!(manager.get(above).map(|window| (window.x, window.y)), Some((35, 50)));
+    manager.move_to(above, 25, 30, 8, screen);
+
+    manager.close(below, 8);
+    manager.compose(&mut writer);
+    assert_eq!(writer.capture_bmp(22, 32, 1, 1).data, [manager.desktop as u8]);
+    assert_eq!(manager.focused(), Some(above));
+
+    // windows can't be moved so far that their title bar leaves the screen.
+    manager.move_to(above, usize::max_value(), usize::max_value(), 8, screen);
+    assert_eq!(manager.get(above).map(|window| (window.x, window.y)), Some((screen.0 - 11, screen.1)));
+    manager.draw(&mut writer, above, |writer| writer.fill_buffer(Color::Green));
+    manager.compose(&mut writer);
+
+    // a surface too big to allocate isn't opened.
+    assert_eq!(manager.open("huge", 0, 0, usize::max_value(), 2), None);
+    assert_eq!(manager.focused(), Some(above));
+    serial_println!("[ok]");
+}